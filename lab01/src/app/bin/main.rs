@@ -1,13 +1,88 @@
-use security::get_key;
+use security::{
+    default_source, AuditLog, Grant, GuardError, LicenseGuard, MachineIdSource, Record, AUDIT_FILE,
+};
 
-const KEY: &str = "XXXXXXXX-XXXX-XXXX-XXXX-XXXXXXXXXXXX";
+use std::path::{Path, PathBuf};
 
-fn main() {
-    if !get_key().eq(KEY) {
-        println!("FAILED");
-        std::process::exit(1);
+security::license_slot!(KEY);
+
+const LICENSE_FILE: &str = "license";
+const LAST_SEEN_FILE: &str = "license.seen";
+const SERVER_FILE: &str = "license.server";
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+const VENDOR_PUBLIC_KEY: &str = include_str!(env!("VENDOR_PUBLIC_KEY"));
+
+/// Directory the app is installed in. The license files are looked up there
+/// rather than in the working directory, which anyone can put files into.
+fn install_dir() -> PathBuf {
+    let exe = std::env::current_exe().expect("failed to locate the app");
+    exe.parent().map(Path::to_path_buf).unwrap_or_default()
+}
+
+fn run(source: Box<dyn MachineIdSource>) {
+    let dir = install_dir();
+    let guard = match LicenseGuard::new(&KEY).source(source).license_file(
+        dir.join(LICENSE_FILE),
+        dir.join(LAST_SEEN_FILE),
+        VENDOR_PUBLIC_KEY,
+    ) {
+        Ok(guard) => guard.server_file(dir.join(SERVER_FILE)),
+        Err(err) => {
+            println!("FAILED: {}", err);
+            std::process::exit(1);
+        }
+    };
+
+    let result = guard.check();
+
+    let outcome = match &result {
+        Ok(Grant::Slot(_)) => "granted by slot".to_string(),
+        Ok(Grant::License(_)) => "granted by license".to_string(),
+        Ok(Grant::Lease(_)) => "granted by lease".to_string(),
+        Err(err) => format!("FAILED: {}", err),
+    };
+    // The app keeps working where the log can not be written.
+    let _ = AuditLog::new(dir.join(AUDIT_FILE)).append(&Record::new(
+        "check",
+        guard.machine(),
+        VERSION,
+        &outcome,
+    ));
+
+    match result {
+        Ok(Grant::Slot(fingerprint)) => {
+            println!("SUCCESS");
+            println!("signed with: {}", fingerprint);
+        }
+        Ok(Grant::License(license)) => {
+            println!("SUCCESS");
+            println!("licensed until: {}", license.not_after);
+            if license.allows("export") {
+                println!("export: enabled");
+            } else {
+                println!("export: disabled");
+            }
+        }
+        Ok(Grant::Lease(lease)) => {
+            // Another instance may take the seat once the lease is lost.
+            lease.keep_alive(|| {
+                println!("FAILED: license lease was lost");
+                std::process::exit(1);
+            });
+            println!("SUCCESS");
+            println!("leased seat: {}", lease.id);
+        }
+        Err(GuardError::Identity(err)) => {
+            println!("FAILED: {}", err);
+            std::process::exit(err.exit_code());
+        }
+        Err(err) => {
+            println!("FAILED: {}", err);
+            std::process::exit(1);
+        }
     }
+}
 
-    println!("SUCCESS");
-    println!("signed with: {}", KEY);
+fn main() {
+    run(default_source());
 }
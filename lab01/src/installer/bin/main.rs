@@ -1,20 +1,394 @@
-use security::get_key;
-use std::fs::File;
-use std::io::Write;
+use rsa::{
+    pkcs1::{DecodeRsaPrivateKey, DecodeRsaPublicKey},
+    RsaPrivateKey, RsaPublicKey,
+};
+use security::{
+    apply_patch, decode_slot, default_source, describe, find_slot, install, request_code,
+    uninstall, verify_image, verify_response, AuditLog, Bundle, Clock, Fingerprint, FixedSource,
+    IdentityError, Installation, License, MachineIdSource, Record, AUDIT_FILE, KEY_LEN,
+    KEY_TEMPLATE, SLOT_LEN,
+};
+use std::path::{Path, PathBuf};
 
-const KEY_TEMPLATE: &[u8] = b"XXXXXXXX-XXXX-XXXX-XXXX-XXXXXXXXXXXX";
+use clap::{Args, Parser, Subcommand, ValueEnum};
 
-fn main() {
-    let app_exe_bytes = include_bytes!(env!("APP_EXE"));
+const LICENSE_FILE: &str = "license";
+/// Last time the app has seen, started along with the license.
+const LAST_SEEN_FILE: &str = "license.seen";
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+const VENDOR_PUBLIC_KEY: &str = include_str!(env!("VENDOR_PUBLIC_KEY"));
+/// Made by `vendor bundle`.
+const APP_BUNDLE: &[u8] = include_bytes!(env!("APP_BUNDLE"));
+
+#[derive(Parser)]
+#[command(author, version, about, long_about = None)]
+struct Cli {
+    #[command(subcommand)]
+    command: Commands,
+}
+
+/// The machine to work for. Defaults to the one the installer runs on.
+#[derive(Args)]
+struct Machine {
+    /// Machine id (board UUID) of another machine
+    #[arg(long, conflicts_with = "id_file")]
+    machine_id: Option<String>,
+    /// File with the output of `installer id` run on another machine
+    #[arg(long)]
+    id_file: Option<PathBuf>,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Target {
+    #[value(name = "app")]
+    App,
+    #[value(name = "app.exe")]
+    AppExe,
+}
+
+impl Target {
+    fn file_name(&self) -> &'static str {
+        match self {
+            Target::App => "app",
+            Target::AppExe => "app.exe",
+        }
+    }
+}
+
+#[derive(Subcommand)]
+enum Commands {
+    /// Prints the components of the machine, to be used with `--id-file`
+    Id,
+    /// Prints the activation request code to send to the vendor
+    Request {
+        #[command(flatten)]
+        machine: Machine,
+    },
+    /// Installs the app bound to the machine, given the vendor response code
+    Activate {
+        #[command(flatten)]
+        machine: Machine,
+        #[arg(short, long, default_value = ".")]
+        output: PathBuf,
+        /// Name of the app binary, defaults to the one in the bundle
+        #[arg(short, long, value_enum)]
+        target: Option<Target>,
+        response: String,
+    },
+    /// Updates an installed app with a vendor patch, keeping its activation
+    Update {
+        #[arg(short, long, default_value = ".")]
+        output: PathBuf,
+        /// Name of the app binary, defaults to the one in the bundle
+        #[arg(short, long, value_enum)]
+        target: Option<Target>,
+        patch: PathBuf,
+    },
+    /// Removes the files put into the output directory by `activate` and `license`
+    Uninstall {
+        #[arg(short, long, default_value = ".")]
+        output: PathBuf,
+    },
+    /// Reports what an installed app is bound to
+    Inspect {
+        #[command(flatten)]
+        machine: Machine,
+        app: PathBuf,
+    },
+    /// Verifies and prints an audit log
+    Audit {
+        #[arg(default_value = AUDIT_FILE)]
+        log: PathBuf,
+    },
+    /// Issues a license file for the machine
+    License {
+        #[command(flatten)]
+        machine: Machine,
+        #[arg(short, long, default_value = ".")]
+        output: PathBuf,
+        #[arg(short = 'P', long, default_value = "private_key.pem")]
+        private_key: PathBuf,
+        #[arg(short, long, default_value_t = 30)]
+        days: i64,
+        #[arg(short, long)]
+        feature: Vec<String>,
+    },
+}
+
+impl Machine {
+    /// The source to work with. Exits with the code of the error if the
+    /// given id is malformed.
+    fn source(&self) -> Box<dyn MachineIdSource> {
+        if let Some(id) = &self.machine_id {
+            return Box::new(identified(FixedSource::new(id)));
+        }
+
+        if let Some(path) = &self.id_file {
+            let content =
+                identified(std::fs::read_to_string(path).map_err(|err| {
+                    IdentityError::Unavailable(format!("{}: {}", path.display(), err))
+                }));
+            return Box::new(identified(FixedSource::parse(&content)));
+        }
+
+        default_source()
+    }
+}
+
+/// Exits with the code of the error if the machine could not be identified.
+fn identified<T>(result: Result<T, IdentityError>) -> T {
+    match result {
+        Ok(value) => value,
+        Err(err) => {
+            eprintln!("Failed to identify the machine: {}", err);
+            std::process::exit(err.exit_code());
+        }
+    }
+}
+
+/// Appends a record to the audit log of the output directory. The work is
+/// done by then, so a log that can not be written is reported with a
+/// failing exit code.
+fn audit(output: &Path, event: &str, source: &dyn MachineIdSource, outcome: &str) {
+    let log = AuditLog::new(output.join(AUDIT_FILE));
+    let result = std::fs::create_dir_all(output)
+        .and_then(|()| log.init())
+        .and_then(|()| log.append(&Record::new(event, source, VERSION, outcome)));
+
+    if let Err(err) = result {
+        eprintln!("Failed to write the audit log: {}", err);
+        std::process::exit(1);
+    }
+}
+
+fn bundle() -> Bundle {
+    match Bundle::from_bytes(APP_BUNDLE) {
+        Ok(bundle) => bundle,
+        Err(err) => {
+            eprintln!("Failed to read the payload: {}", err);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Path the app binary is installed at.
+fn main_path(bundle: &Bundle, target: Option<Target>) -> String {
+    match target {
+        Some(target) => target.file_name().to_string(),
+        None => bundle.main.clone(),
+    }
+}
+
+fn run_install(source: &dyn MachineIdSource, output: &Path, target: Option<Target>) {
+    let fingerprint = identified(Fingerprint::of(source));
+    let bundle = bundle();
+    let files = match bundle.files() {
+        Ok(files) => files,
+        Err(err) => {
+            eprintln!("Failed to unpack the payload: {}", err);
+            std::process::exit(1);
+        }
+    };
 
-    println!("Installing {} bytes...", app_exe_bytes.len());
     println!("Signing app...");
-    let key_pos = app_exe_bytes.windows(KEY_TEMPLATE.len()).position(|w| w == KEY_TEMPLATE).unwrap();
+    let main = files.iter().find(|file| file.path == bundle.main).unwrap();
+    let app = match install(&main.data, &fingerprint) {
+        Ok(app) => app,
+        Err(err) => {
+            eprintln!("Failed to find the license slot: {}", err);
+            audit(output, "install", source, &format!("FAILED: {}", err));
+            std::process::exit(1);
+        }
+    };
 
-    let mut app = File::create("app.exe").unwrap();
-    app.write(&app_exe_bytes[..key_pos]).unwrap();
-    app.write(&get_key().into_bytes()).unwrap();
-    app.write(&app_exe_bytes[key_pos+KEY_TEMPLATE.len()..]).unwrap();
+    println!("Installing {} files...", files.len());
+    // The installation is dropped before exiting, which removes what was
+    // staged of a failed install.
+    let result = Installation::new(output).and_then(|mut installation| {
+        for file in files.iter().filter(|file| file.path != bundle.main) {
+            installation.put_mode(&file.path, &file.data, file.mode)?;
+        }
+        installation.put_mode(&main_path(&bundle, target), &app, main.mode)?;
+        installation.commit()
+    });
+    if let Err(err) = result {
+        eprintln!("Failed to install the app: {}", err);
+        audit(output, "install", source, &format!("FAILED: {}", err));
+        std::process::exit(1);
+    }
+    audit(output, "install", source, "installed");
 
     println!("Finished!");
 }
+
+fn run_inspect(source: &dyn MachineIdSource, app: &Path) {
+    let binary = std::fs::read(app).expect("failed to read app");
+    let range = match find_slot(&binary) {
+        Ok(range) => range,
+        Err(err) => {
+            println!("slot: {}", err);
+            std::process::exit(1);
+        }
+    };
+    println!("slot: {} bytes at {:#x}", SLOT_LEN, range.start);
+
+    let slot: [u8; SLOT_LEN] = binary[range].try_into().unwrap();
+    if slot == KEY_TEMPLATE {
+        println!("state: unbound (placeholder)");
+        return;
+    }
+
+    let payload = decode_slot(&slot);
+    let fingerprint = match std::str::from_utf8(&payload[..KEY_LEN])
+        .ok()
+        .and_then(Fingerprint::from_hex)
+    {
+        Some(fingerprint) => fingerprint,
+        None => {
+            println!("state: corrupted");
+            std::process::exit(1);
+        }
+    };
+    println!("state: bound");
+    println!("fingerprint: {}", fingerprint.to_hex());
+
+    match verify_image(&binary, &slot) {
+        Ok(()) => println!("integrity: ok"),
+        Err(err) => println!("integrity: {}", err),
+    }
+
+    for (component, matched) in identified(fingerprint.compare(source)) {
+        let status = match matched {
+            Some(true) => "match",
+            Some(false) => "mismatch",
+            None => "not recorded",
+        };
+        println!("{}: {}", component.name(), status);
+    }
+
+    if identified(fingerprint.matches(source)) {
+        println!("machine: accepted");
+    } else {
+        println!("machine: rejected");
+    }
+}
+
+fn main() {
+    match Cli::parse().command {
+        Commands::Id => {
+            print!("{}", identified(describe(&default_source())));
+        }
+        Commands::Request { machine } => {
+            println!("{}", identified(request_code(&machine.source())));
+        }
+        Commands::Activate {
+            machine,
+            output,
+            target,
+            response,
+        } => {
+            let source = machine.source();
+            let public_key =
+                RsaPublicKey::from_pkcs1_pem(VENDOR_PUBLIC_KEY).expect("failed to read vendor key");
+
+            if !identified(verify_response(&source, &response, &public_key)) {
+                println!("Response code does not match the machine");
+                audit(
+                    &output,
+                    "install",
+                    &source,
+                    "FAILED: response code rejected",
+                );
+                std::process::exit(1);
+            }
+
+            run_install(&source, &output, target);
+        }
+        Commands::Update {
+            output,
+            target,
+            patch,
+        } => {
+            let public_key =
+                RsaPublicKey::from_pkcs1_pem(VENDOR_PUBLIC_KEY).expect("failed to read vendor key");
+            let name = main_path(&bundle(), target);
+            let installed = std::fs::read(output.join(&name)).expect("failed to read app");
+            let patch = std::fs::read(patch).expect("failed to read patch");
+
+            let app = match apply_patch(&installed, &patch, &public_key) {
+                Ok(app) => app,
+                Err(err) => {
+                    eprintln!("Failed to update: {}", err);
+                    audit(
+                        &output,
+                        "update",
+                        &default_source(),
+                        &format!("FAILED: {}", err),
+                    );
+                    std::process::exit(1);
+                }
+            };
+
+            let mut installation =
+                Installation::new(&output).expect("failed to open output directory");
+            installation
+                .put(&name, &app, true)
+                .expect("failed to write app");
+            installation.commit().expect("failed to install app");
+            audit(&output, "update", &default_source(), "updated");
+
+            println!("Updated!");
+        }
+        Commands::Uninstall { output } => {
+            let kept = uninstall(&output).expect("failed to uninstall");
+            for path in &kept {
+                println!("Kept modified {}", path.display());
+            }
+            let outcome = format!("uninstalled, {} modified files kept", kept.len());
+            audit(&output, "uninstall", &default_source(), &outcome);
+
+            println!("Uninstalled!");
+        }
+        Commands::Inspect { machine, app } => {
+            run_inspect(&machine.source(), &app);
+        }
+        Commands::Audit { log } => match AuditLog::new(log).verify() {
+            Ok(records) => {
+                for record in records {
+                    println!("{}", record);
+                }
+                println!("log: intact");
+            }
+            Err(err) => {
+                println!("log: {}", err);
+                std::process::exit(1);
+            }
+        },
+        Commands::License {
+            machine,
+            output,
+            private_key,
+            days,
+            feature,
+        } => {
+            let private_key = RsaPrivateKey::read_pkcs1_pem_file(private_key)
+                .expect("failed to read private key");
+
+            let source = machine.source();
+            let license = identified(License::new(&source, days, feature));
+            let mut installation =
+                Installation::new(&output).expect("failed to create output directory");
+            installation
+                .put(LICENSE_FILE, license.sign(&private_key).as_bytes(), false)
+                .expect("failed to write license");
+            installation.commit().expect("failed to install license");
+            Clock::new(output.join(LAST_SEEN_FILE))
+                .start()
+                .expect("failed to start license clock");
+            let outcome = format!("licensed until {}", license.not_after);
+            audit(&output, "license", &source, &outcome);
+
+            println!("Licensed until {}", license.not_after);
+        }
+    }
+}
@@ -0,0 +1,88 @@
+//! Offline activation.
+//!
+//! The installer shows a request code derived from the machine token. The
+//! vendor signs it with its private key and sends the signature back as the
+//! response code, which the installer checks against its own request code
+//! before binding the app.
+
+use rsa::{RsaPrivateKey, RsaPublicKey};
+
+use crate::identity::{IdentityError, MachineIdSource};
+use crate::signing::{sign, verify_signature};
+use crate::token::token;
+
+/// Number of token bytes in a request code.
+const REQUEST_LEN: usize = 10;
+
+/// Request code of the machine reported by `source`, e.g.
+/// `1A2B-3C4D-5E6F-7A8B-9C0D`.
+pub fn request_code(source: &dyn MachineIdSource) -> Result<String, IdentityError> {
+    let digits = hex::encode_upper(&token(&source.machine_id()?)[..REQUEST_LEN]);
+    let groups: Vec<&str> = (0..digits.len())
+        .step_by(4)
+        .map(|i| &digits[i..i + 4])
+        .collect();
+
+    Ok(groups.join("-"))
+}
+
+/// Drops separators and case, so codes typed by hand are accepted. Returns
+/// `None` if `code` is not a request code at all.
+fn normalize_request(code: &str) -> Option<String> {
+    let digits: String = code
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != '-')
+        .map(|c| c.to_ascii_uppercase())
+        .collect();
+
+    if digits.len() != 2 * REQUEST_LEN || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    Some(digits)
+}
+
+/// Vendor side: turns a request code into a response code.
+pub fn respond(request: &str, key: &RsaPrivateKey) -> Option<String> {
+    let request = normalize_request(request)?;
+
+    Some(hex::encode(sign(key, request.as_bytes())))
+}
+
+/// Checks that `response` was issued by the vendor for the machine reported
+/// by `source`.
+pub fn verify_response(
+    source: &dyn MachineIdSource,
+    response: &str,
+    key: &RsaPublicKey,
+) -> Result<bool, IdentityError> {
+    let request = normalize_request(&request_code(source)?).unwrap();
+
+    Ok(match hex::decode(response.trim()) {
+        Ok(sign) => verify_signature(key, request.as_bytes(), &sign),
+        Err(_) => false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    // Note this useful idiom: importing names from outer (for mod tests) scope.
+    use super::*;
+    use crate::identity::FixedSource;
+
+    #[test]
+    fn test_request_response() {
+        let mut rng = rand::thread_rng();
+        let private_key = RsaPrivateKey::new(&mut rng, 512).unwrap();
+        let public_key = private_key.to_public_key();
+        let machine = FixedSource::new("01234567-89AB-CDEF-0123-456789ABCDEF").unwrap();
+        let other = FixedSource::new("FEDCBA98-7654-3210-FEDC-BA9876543210").unwrap();
+
+        let request = request_code(&machine).unwrap().to_lowercase();
+        let response = respond(&request, &private_key).unwrap();
+
+        assert!(verify_response(&machine, &response, &public_key).unwrap());
+        assert!(!verify_response(&other, &response, &public_key).unwrap());
+        assert!(respond("not a code", &private_key).is_none());
+    }
+}
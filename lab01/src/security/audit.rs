@@ -0,0 +1,363 @@
+//! Hash-chained audit log of bindings and license checks.
+//!
+//! Every record is a line of tab separated fields: the unix time, the event,
+//! the machine, the app version, the outcome and the chain value. The machine
+//! is a hash of its fingerprint and the chain value is the keyed digest of
+//! the previous one and the other fields, so editing or removing a record
+//! breaks the chain of every record after it.
+//!
+//! Records cut off the end would still leave a valid chain, so the number of
+//! records and the last chain value are also kept in a head file next to the
+//! log, `audit.head` for `audit.log`, keyed so it can not be rewritten to
+//! match a shorter log. Only the installer starts a log: a log that is gone,
+//! or whose head is, is reported rather than silently started over.
+//!
+//! Apps started at the same time take turns through a lock on `audit.lock`,
+//! and the head is replaced in one rename, so they can not break the chain.
+
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+
+use crate::date::{unix_now, Date};
+use crate::fingerprint::Fingerprint;
+use crate::identity::MachineIdSource;
+use crate::installation::replace_file;
+use crate::token::keyed_digest;
+
+pub const AUDIT_FILE: &str = "audit.log";
+
+/// Extension of the head file, replacing the one of the log.
+const HEAD_EXTENSION: &str = "head";
+
+/// Extension of the file locked while the log is written.
+const LOCK_EXTENSION: &str = "lock";
+
+/// Chain value the first record is linked to.
+const GENESIS: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// Hex digits of the fingerprint hash kept in a record.
+const MACHINE_LEN: usize = 16;
+
+#[derive(Debug)]
+pub enum AuditError {
+    Unreadable(io::Error),
+    /// The record at the given line can not be parsed.
+    Malformed(usize),
+    /// The record at the given line, or one before it, was edited or removed.
+    Broken(usize),
+    /// The log was deleted.
+    Missing,
+    /// Records were cut off the end of the log, or its head was removed. The
+    /// log ends after the given number of records.
+    Truncated(usize),
+}
+
+impl fmt::Display for AuditError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AuditError::Unreadable(err) => write!(f, "failed to read audit log: {}", err),
+            AuditError::Malformed(line) => write!(f, "malformed audit record at line {}", line),
+            AuditError::Broken(line) => write!(f, "audit log is tampered at line {}", line),
+            AuditError::Missing => write!(f, "audit log was deleted"),
+            AuditError::Truncated(records) => {
+                write!(f, "audit log was cut short, {} records are left", records)
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Record {
+    pub timestamp: u64,
+    pub event: String,
+    pub machine: String,
+    pub version: String,
+    pub outcome: String,
+}
+
+/// Makes `value` fit into a single field.
+fn field(value: &str) -> String {
+    value.replace(['\t', '\n', '\r'], " ")
+}
+
+impl Record {
+    /// Record of `event` on the machine reported by `source`, happening now.
+    pub fn new(event: &str, source: &dyn MachineIdSource, version: &str, outcome: &str) -> Record {
+        let machine = match Fingerprint::of(source) {
+            Ok(fingerprint) => {
+                hex::encode(Sha256::digest(fingerprint.to_hex()))[..MACHINE_LEN].to_string()
+            }
+            Err(_) => "unknown".to_string(),
+        };
+
+        Record {
+            timestamp: unix_now(),
+            event: field(event),
+            machine,
+            version: field(version),
+            outcome: field(outcome),
+        }
+    }
+
+    fn body(&self) -> String {
+        format!(
+            "{}\t{}\t{}\t{}\t{}",
+            self.timestamp, self.event, self.machine, self.version, self.outcome
+        )
+    }
+
+    fn chain(&self, previous: &str) -> String {
+        hex::encode(keyed_digest(&[
+            b"audit:",
+            previous.as_bytes(),
+            b":",
+            self.body().as_bytes(),
+        ]))
+    }
+}
+
+impl fmt::Display for Record {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} ({}) {} machine {} version {}: {}",
+            self.timestamp,
+            Date::from_unix(self.timestamp),
+            self.event,
+            self.machine,
+            self.version,
+            self.outcome
+        )
+    }
+}
+
+/// Splits a line into the record and its chain value.
+fn parse_line(line: &str) -> Option<(Record, &str)> {
+    let fields: Vec<&str> = line.split('\t').collect();
+    let [timestamp, event, machine, version, outcome, chain] = fields[..] else {
+        return None;
+    };
+
+    let record = Record {
+        timestamp: timestamp.parse().ok()?,
+        event: event.to_string(),
+        machine: machine.to_string(),
+        version: version.to_string(),
+        outcome: outcome.to_string(),
+    };
+    Some((record, chain))
+}
+
+/// Head of a log of `records` records ending with `chain`.
+fn head(records: usize, chain: &str) -> String {
+    let tag = keyed_digest(&[
+        b"audit-head:",
+        records.to_string().as_bytes(),
+        b":",
+        chain.as_bytes(),
+    ]);
+    format!("{} {}\n", records, hex::encode(tag))
+}
+
+/// Number of records of `content` and its last chain value.
+fn tail(content: &str) -> (usize, &str) {
+    let previous = content
+        .lines()
+        .last()
+        .and_then(|line| line.rsplit_once('\t'))
+        .map_or(GENESIS, |(_, chain)| chain);
+
+    (content.lines().count(), previous)
+}
+
+pub struct AuditLog {
+    path: PathBuf,
+    head: PathBuf,
+    lock: PathBuf,
+}
+
+impl AuditLog {
+    pub fn new<P: AsRef<Path>>(path: P) -> AuditLog {
+        AuditLog {
+            path: path.as_ref().to_path_buf(),
+            head: path.as_ref().with_extension(HEAD_EXTENSION),
+            lock: path.as_ref().with_extension(LOCK_EXTENSION),
+        }
+    }
+
+    /// Locks the log until the returned file is dropped, against everyone
+    /// else if `exclusive` or else against writers only. The lock goes away
+    /// with the process holding it.
+    fn locked(&self, exclusive: bool) -> io::Result<File> {
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&self.lock)?;
+        if exclusive {
+            file.lock()?;
+        } else {
+            file.lock_shared()?;
+        }
+        Ok(file)
+    }
+
+    /// Starts an empty log, unless there is a log or a head already.
+    pub fn init(&self) -> io::Result<()> {
+        let _lock = self.locked(true)?;
+        if self.path.exists() || self.head.exists() {
+            return Ok(());
+        }
+
+        fs::write(&self.path, "")?;
+        replace_file(&self.head, head(0, GENESIS).as_bytes())
+    }
+
+    /// Appends `record`, linked to the last record of the log. The log must
+    /// have been started with `init` and match its head.
+    pub fn append(&self, record: &Record) -> io::Result<()> {
+        let _lock = self.locked(true)?;
+        let content = fs::read_to_string(&self.path)?;
+        let (records, previous) = tail(&content);
+
+        // Moving the head past a cut would hide it.
+        if fs::read_to_string(&self.head)? != head(records, previous) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "audit log does not match its head",
+            ));
+        }
+
+        let chain = record.chain(previous);
+        let mut file = OpenOptions::new().append(true).open(&self.path)?;
+        writeln!(file, "{}\t{}", record.body(), chain)?;
+        replace_file(&self.head, head(records + 1, &chain).as_bytes())
+    }
+
+    /// Reads the records, checking that the chain is intact and that the log
+    /// ends where its head says.
+    pub fn verify(&self) -> Result<Vec<Record>, AuditError> {
+        // A record written in between the log and the head would look like
+        // a cut.
+        let _lock = self.locked(false).map_err(AuditError::Unreadable)?;
+        let content = match fs::read_to_string(&self.path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Err(AuditError::Missing),
+            Err(err) => return Err(AuditError::Unreadable(err)),
+        };
+        let mut previous = GENESIS.to_string();
+        let mut records = vec![];
+
+        for (i, line) in content.lines().enumerate() {
+            let (record, chain) = parse_line(line).ok_or(AuditError::Malformed(i + 1))?;
+
+            let expected = record.chain(&previous);
+            if !bool::from(expected.as_bytes().ct_eq(chain.as_bytes())) {
+                return Err(AuditError::Broken(i + 1));
+            }
+
+            previous = expected;
+            records.push(record);
+        }
+
+        let expected = head(records.len(), &previous);
+        match fs::read_to_string(&self.head) {
+            Ok(content) if bool::from(content.as_bytes().ct_eq(expected.as_bytes())) => {}
+            Ok(_) => return Err(AuditError::Truncated(records.len())),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                return Err(AuditError::Truncated(records.len()))
+            }
+            Err(err) => return Err(AuditError::Unreadable(err)),
+        }
+
+        Ok(records)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    // Note this useful idiom: importing names from outer (for mod tests) scope.
+    use super::*;
+    use crate::identity::FixedSource;
+
+    #[test]
+    fn test_detects_tampering() {
+        let path = std::env::temp_dir().join(format!("security-audit-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_file(path.with_extension(HEAD_EXTENSION));
+        let log = AuditLog::new(&path);
+        log.init().unwrap();
+        let machine = FixedSource::new("01234567-89AB-CDEF-0123-456789ABCDEF").unwrap();
+
+        for outcome in ["installed", "granted", "FAILED: license expired"] {
+            log.append(&Record::new("check", &machine, "0.1.0", outcome))
+                .unwrap();
+        }
+        let records = log.verify().unwrap();
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[2].outcome, "FAILED: license expired");
+
+        let content = fs::read_to_string(&path).unwrap();
+        fs::write(&path, content.replacen("granted", "GRANTED", 1)).unwrap();
+        assert!(matches!(log.verify(), Err(AuditError::Broken(2))));
+
+        let lines: Vec<&str> = content.lines().collect();
+        fs::write(&path, format!("{}\n{}\n", lines[0], lines[2])).unwrap();
+        assert!(matches!(log.verify(), Err(AuditError::Broken(2))));
+
+        // Cutting records off the end, or the whole log, is noticed too.
+        fs::write(&path, format!("{}\n{}\n", lines[0], lines[1])).unwrap();
+        assert!(matches!(log.verify(), Err(AuditError::Truncated(2))));
+        assert!(log
+            .append(&Record::new("check", &machine, "0.1.0", "granted"))
+            .is_err());
+        fs::write(&path, "").unwrap();
+        assert!(matches!(log.verify(), Err(AuditError::Truncated(0))));
+
+        fs::remove_file(&path).unwrap();
+        log.init().unwrap();
+        assert!(matches!(log.verify(), Err(AuditError::Missing)));
+        assert!(log
+            .append(&Record::new("check", &machine, "0.1.0", "granted"))
+            .is_err());
+
+        fs::remove_file(path.with_extension(HEAD_EXTENSION)).unwrap();
+        fs::remove_file(path.with_extension(LOCK_EXTENSION)).unwrap();
+    }
+
+    #[test]
+    fn test_concurrent_appends() {
+        let path = std::env::temp_dir().join(format!("security-audit-mt-{}", std::process::id()));
+        let log = AuditLog::new(&path);
+        log.init().unwrap();
+
+        let threads: Vec<_> = (0..8)
+            .map(|i| {
+                let path = path.clone();
+                std::thread::spawn(move || {
+                    let log = AuditLog::new(&path);
+                    let machine = FixedSource::new("01234567-89AB-CDEF-0123-456789ABCDEF").unwrap();
+                    for _ in 0..20 {
+                        log.append(&Record::new("check", &machine, "0.1.0", &i.to_string()))
+                            .unwrap();
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        assert_eq!(log.verify().unwrap().len(), 160);
+
+        fs::remove_file(&path).unwrap();
+        for extension in [HEAD_EXTENSION, LOCK_EXTENSION] {
+            fs::remove_file(path.with_extension(extension)).unwrap();
+        }
+    }
+}
@@ -0,0 +1,191 @@
+//! Payload bundles the installer unpacks.
+//!
+//! A bundle is a postcard encoded list of files with their install path, Unix
+//! mode and SHA-256. Files may be deflate compressed; the hash is taken over
+//! the uncompressed content. One of the files is the main binary, the only
+//! one the license slot is patched in.
+
+use std::fmt;
+
+use miniz_oxide::deflate::compress_to_vec;
+use miniz_oxide::inflate::decompress_to_vec;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Compression level passed to deflate.
+const LEVEL: u8 = 9;
+
+#[derive(Debug)]
+pub enum BundleError {
+    Malformed,
+    BadPath(String),
+    Duplicate(String),
+    MissingMain(String),
+    Corrupted(String),
+}
+
+impl fmt::Display for BundleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BundleError::Malformed => write!(f, "malformed bundle"),
+            BundleError::BadPath(path) => write!(f, "bad path in bundle: {:?}", path),
+            BundleError::Duplicate(path) => write!(f, "{} is bundled twice", path),
+            BundleError::MissingMain(path) => write!(f, "main binary {} is not bundled", path),
+            BundleError::Corrupted(path) => write!(f, "{} is corrupted in the bundle", path),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    path: String,
+    mode: u32,
+    sha256: [u8; 32],
+    compressed: bool,
+    data: Vec<u8>,
+}
+
+/// A file unpacked from a bundle.
+pub struct BundleFile {
+    pub path: String,
+    pub mode: u32,
+    pub data: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Bundle {
+    /// Path of the main binary.
+    pub main: String,
+    entries: Vec<Entry>,
+}
+
+/// Accepts relative paths with `/` separators that stay inside the target
+/// directory.
+fn valid_path(path: &str) -> bool {
+    !path.is_empty()
+        && path.split('/').all(|part| {
+            !part.is_empty() && part != "." && part != ".." && !part.contains(['\\', ':'])
+        })
+}
+
+impl Bundle {
+    pub fn new(main: &str) -> Bundle {
+        Bundle {
+            main: main.to_string(),
+            entries: vec![],
+        }
+    }
+
+    /// Adds a file, compressed if `compress` is set and it makes it smaller.
+    pub fn add(
+        &mut self,
+        path: &str,
+        data: &[u8],
+        mode: u32,
+        compress: bool,
+    ) -> Result<(), BundleError> {
+        if !valid_path(path) {
+            return Err(BundleError::BadPath(path.to_string()));
+        }
+        if self.entries.iter().any(|entry| entry.path == path) {
+            return Err(BundleError::Duplicate(path.to_string()));
+        }
+
+        let sha256 = Sha256::digest(data).into();
+        let (compressed, data) = match compress.then(|| compress_to_vec(data, LEVEL)) {
+            Some(compressed) if compressed.len() < data.len() => (true, compressed),
+            _ => (false, data.to_vec()),
+        };
+
+        self.entries.push(Entry {
+            path: path.to_string(),
+            mode,
+            sha256,
+            compressed,
+            data,
+        });
+        Ok(())
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        postcard::to_allocvec(self).unwrap()
+    }
+
+    /// Parses a bundle and checks its paths. Contents are checked by `files`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Bundle, BundleError> {
+        let bundle: Bundle = postcard::from_bytes(bytes).map_err(|_| BundleError::Malformed)?;
+
+        for (i, entry) in bundle.entries.iter().enumerate() {
+            if !valid_path(&entry.path) {
+                return Err(BundleError::BadPath(entry.path.clone()));
+            }
+            if bundle.entries[..i].iter().any(|e| e.path == entry.path) {
+                return Err(BundleError::Duplicate(entry.path.clone()));
+            }
+        }
+
+        if !bundle.entries.iter().any(|entry| entry.path == bundle.main) {
+            return Err(BundleError::MissingMain(bundle.main));
+        }
+
+        Ok(bundle)
+    }
+
+    /// Unpacks the files, checking them against their hashes.
+    pub fn files(&self) -> Result<Vec<BundleFile>, BundleError> {
+        self.entries
+            .iter()
+            .map(|entry| {
+                let data = data_of(entry.compressed, &entry.data)
+                    .map_err(|_| BundleError::Corrupted(entry.path.clone()))?;
+                if Sha256::digest(&data).as_slice() != entry.sha256 {
+                    return Err(BundleError::Corrupted(entry.path.clone()));
+                }
+
+                Ok(BundleFile {
+                    path: entry.path.clone(),
+                    mode: entry.mode,
+                    data,
+                })
+            })
+            .collect()
+    }
+}
+
+fn data_of(compressed: bool, data: &[u8]) -> Result<Vec<u8>, BundleError> {
+    if compressed {
+        decompress_to_vec(data).map_err(|_| BundleError::Malformed)
+    } else {
+        Ok(data.to_vec())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    // Note this useful idiom: importing names from outer (for mod tests) scope.
+    use super::*;
+
+    #[test]
+    fn test_pack_unpack() {
+        let config = b"level = 1\n".repeat(100);
+
+        let mut bundle = Bundle::new("app");
+        bundle.add("app", b"binary", 0o755, true).unwrap();
+        bundle.add("etc/app.toml", &config, 0o644, true).unwrap();
+        assert!(bundle.add("../escape", b"", 0o644, false).is_err());
+        assert!(bundle.add("app", b"", 0o644, false).is_err());
+        assert!(bundle.entries[1].compressed);
+
+        let bundle = Bundle::from_bytes(&bundle.to_bytes()).unwrap();
+        let files = bundle.files().unwrap();
+        assert_eq!(files[0].path, "app");
+        assert_eq!(files[0].data, b"binary");
+        assert_eq!(files[1].mode, 0o644);
+        assert_eq!(files[1].data, config);
+
+        let mut corrupted = bundle;
+        corrupted.entries[0].data[0] ^= 1;
+        assert!(corrupted.files().is_err());
+        assert!(Bundle::from_bytes(&Bundle::new("app").to_bytes()).is_err());
+    }
+}
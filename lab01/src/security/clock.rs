@@ -0,0 +1,136 @@
+//! Clock rollback detection.
+//!
+//! `Clock` persists the latest time the app has seen, so that setting the
+//! system clock back does not extend a time limited license. The stored value
+//! is tagged with the vendor secret and an edited file counts as a rollback.
+//!
+//! The record is started along with the license by `Clock::start`. From then
+//! on a missing or unreadable record counts as a rollback too, as removing it
+//! would otherwise reset the clock.
+//!
+//! The tag only proves that the app wrote the record, not when. Putting back
+//! an older copy of the file still winds the clock back to that copy, so a
+//! trial can be stretched by someone who keeps backups of it.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::date::unix_now;
+use crate::installation::replace_file;
+use crate::license::LicenseError;
+use crate::token::{token, verify_token};
+
+/// How far back the clock may go without being reported, to tolerate NTP
+/// corrections and the like.
+const TOLERANCE_SECS: u64 = 60 * 60;
+
+pub struct Clock {
+    path: PathBuf,
+}
+
+impl Clock {
+    pub fn new<P: AsRef<Path>>(path: P) -> Clock {
+        Clock {
+            path: path.as_ref().to_path_buf(),
+        }
+    }
+
+    fn tagged(secs: u64) -> String {
+        format!("last-seen:{}", secs)
+    }
+
+    /// Records the current time, unless a record exists already.
+    pub fn start(&self) -> io::Result<()> {
+        match fs::metadata(&self.path) {
+            Ok(_) => Ok(()),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => self.write(unix_now()),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn write(&self, seen: u64) -> io::Result<()> {
+        let tag = hex::encode(token(&Clock::tagged(seen)));
+        replace_file(&self.path, format!("{} {}\n", seen, tag).as_bytes())
+    }
+
+    fn last_seen(&self) -> Result<u64, LicenseError> {
+        let content = fs::read_to_string(&self.path).map_err(|_| LicenseError::ClockRollback)?;
+
+        let (secs, tag) = content
+            .trim()
+            .split_once(' ')
+            .ok_or(LicenseError::ClockRollback)?;
+        let secs: u64 = secs.parse().map_err(|_| LicenseError::ClockRollback)?;
+        let tag = hex::decode(tag).map_err(|_| LicenseError::ClockRollback)?;
+
+        if !verify_token(&Clock::tagged(secs), &tag) {
+            return Err(LicenseError::ClockRollback);
+        }
+
+        Ok(secs)
+    }
+
+    /// Records `now` and returns the time licenses should be checked against.
+    fn observe(&self, now: u64) -> Result<u64, LicenseError> {
+        let last_seen = self.last_seen()?;
+
+        if now + TOLERANCE_SECS < last_seen {
+            return Err(LicenseError::ClockRollback);
+        }
+
+        let seen = now.max(last_seen);
+        // A read-only install still works, the clock just does not move on.
+        let _ = self.write(seen);
+
+        Ok(seen)
+    }
+
+    /// Current unix time, or an error if the system clock is behind the
+    /// latest time seen before.
+    pub fn now(&self) -> Result<u64, LicenseError> {
+        self.observe(unix_now())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    // Note this useful idiom: importing names from outer (for mod tests) scope.
+    use super::*;
+
+    #[test]
+    fn test_detects_rollback() {
+        let path = std::env::temp_dir().join(format!("security-clock-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let clock = Clock::new(&path);
+        let day = 24 * 60 * 60;
+
+        // Without a record started there is nothing to trust.
+        assert!(matches!(
+            clock.observe(10 * day),
+            Err(LicenseError::ClockRollback)
+        ));
+        clock.start().unwrap();
+        assert!(clock.now().is_ok());
+
+        clock.write(10 * day).unwrap();
+        assert_eq!(clock.observe(10 * day).unwrap(), 10 * day);
+        assert_eq!(clock.observe(10 * day - 60).unwrap(), 10 * day);
+        assert!(matches!(
+            clock.observe(9 * day),
+            Err(LicenseError::ClockRollback)
+        ));
+
+        fs::write(&path, format!("{} 00\n", 20 * day)).unwrap();
+        assert!(matches!(
+            clock.observe(10 * day),
+            Err(LicenseError::ClockRollback)
+        ));
+
+        fs::remove_file(&path).unwrap();
+        assert!(matches!(
+            clock.observe(10 * day),
+            Err(LicenseError::ClockRollback)
+        ));
+    }
+}
@@ -0,0 +1,96 @@
+//! Calendar dates as days since the unix epoch, written as `YYYY-MM-DD`.
+
+use std::fmt;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const SECS_PER_DAY: u64 = 24 * 60 * 60;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Date {
+    days: i64,
+}
+
+impl Date {
+    pub fn today() -> Date {
+        Date::from_unix(unix_now())
+    }
+
+    /// Day of the unix timestamp `secs`.
+    pub fn from_unix(secs: u64) -> Date {
+        Date {
+            days: (secs / SECS_PER_DAY) as i64,
+        }
+    }
+
+    pub fn from_ymd(year: i64, month: i64, day: i64) -> Date {
+        // http://howardhinnant.github.io/date_algorithms.html#days_from_civil
+        let year = if month <= 2 { year - 1 } else { year };
+        let era = if year >= 0 { year } else { year - 399 } / 400;
+        let yoe = year - era * 400;
+        let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+        let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+
+        Date {
+            days: era * 146097 + doe - 719468,
+        }
+    }
+
+    pub fn ymd(&self) -> (i64, i64, i64) {
+        // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+        let z = self.days + 719468;
+        let era = if z >= 0 { z } else { z - 146096 } / 146097;
+        let doe = z - era * 146097;
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+        (year, month, day)
+    }
+
+    pub fn add_days(&self, days: i64) -> Date {
+        Date {
+            days: self.days + days,
+        }
+    }
+}
+
+/// Seconds since the unix epoch by the system clock.
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+impl fmt::Display for Date {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (year, month, day) = self.ymd();
+        write!(f, "{:04}-{:02}-{:02}", year, month, day)
+    }
+}
+
+impl FromStr for Date {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Date, String> {
+        let parts: Vec<i64> = s
+            .split('-')
+            .map(|part| part.parse().map_err(|_| format!("bad date `{}`", s)))
+            .collect::<Result<_, _>>()?;
+
+        if parts.len() != 3 {
+            return Err(format!("bad date `{}`", s));
+        }
+
+        let date = Date::from_ymd(parts[0], parts[1], parts[2]);
+        if date.ymd() != (parts[0], parts[1], parts[2]) {
+            return Err(format!("no such date `{}`", s));
+        }
+
+        Ok(date)
+    }
+}
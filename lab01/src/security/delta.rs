@@ -0,0 +1,133 @@
+//! Binary diff of two builds.
+//!
+//! The new build is described as a list of operations copying ranges of the
+//! old build or inserting literal bytes. Matching ranges are found by hashing
+//! the old build in fixed size blocks and looking up a rolling hash of every
+//! window of the new build.
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+const BLOCK: usize = 32;
+const BASE: u64 = 257;
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub enum Op {
+    Copy { offset: u64, len: u64 },
+    Insert(Vec<u8>),
+}
+
+fn block_hash(block: &[u8]) -> u64 {
+    block
+        .iter()
+        .fold(0u64, |h, b| h.wrapping_mul(BASE).wrapping_add(*b as u64))
+}
+
+pub fn diff(old: &[u8], new: &[u8]) -> Vec<Op> {
+    let mut blocks: HashMap<u64, usize> = HashMap::new();
+    for (i, block) in old.chunks_exact(BLOCK).enumerate() {
+        blocks.entry(block_hash(block)).or_insert(i * BLOCK);
+    }
+
+    // BASE^(BLOCK - 1), the weight of the byte leaving the window.
+    let top = (1..BLOCK).fold(1u64, |p, _| p.wrapping_mul(BASE));
+
+    let mut ops = vec![];
+    let mut insert = vec![];
+    let mut i = 0;
+    let mut hash = None;
+
+    while i + BLOCK <= new.len() {
+        let h = *hash.get_or_insert_with(|| block_hash(&new[i..i + BLOCK]));
+
+        let found = blocks
+            .get(&h)
+            .filter(|&&offset| old[offset..offset + BLOCK] == new[i..i + BLOCK]);
+
+        if let Some(&offset) = found {
+            let len = old[offset..]
+                .iter()
+                .zip(&new[i..])
+                .take_while(|(a, b)| a == b)
+                .count();
+
+            if !insert.is_empty() {
+                ops.push(Op::Insert(std::mem::take(&mut insert)));
+            }
+            ops.push(Op::Copy {
+                offset: offset as u64,
+                len: len as u64,
+            });
+
+            i += len;
+            hash = None;
+            continue;
+        }
+
+        insert.push(new[i]);
+        if i + BLOCK < new.len() {
+            let out = (new[i] as u64).wrapping_mul(top);
+            hash = Some(
+                h.wrapping_sub(out)
+                    .wrapping_mul(BASE)
+                    .wrapping_add(new[i + BLOCK] as u64),
+            );
+        }
+        i += 1;
+    }
+
+    insert.extend_from_slice(&new[i..]);
+    if !insert.is_empty() {
+        ops.push(Op::Insert(insert));
+    }
+
+    ops
+}
+
+/// Rebuilds the new build from `old`, or returns `None` if an operation
+/// refers outside of it.
+pub fn apply(old: &[u8], ops: &[Op]) -> Option<Vec<u8>> {
+    let mut new = vec![];
+
+    for op in ops {
+        match op {
+            Op::Copy { offset, len } => {
+                let start = usize::try_from(*offset).ok()?;
+                let end = start.checked_add(usize::try_from(*len).ok()?)?;
+                new.extend_from_slice(old.get(start..end)?);
+            }
+            Op::Insert(bytes) => new.extend_from_slice(bytes),
+        }
+    }
+
+    Some(new)
+}
+
+#[cfg(test)]
+mod tests {
+    // Note this useful idiom: importing names from outer (for mod tests) scope.
+    use super::*;
+
+    #[test]
+    fn test_diff_apply() {
+        let old: Vec<u8> = (0..10_000u32).map(|i| (i * 7 % 251) as u8).collect();
+        let mut new = old.clone();
+        new[100] ^= 0xff;
+        new.splice(5000..5000, b"inserted".iter().cloned());
+        new.drain(8000..8100);
+        new.extend_from_slice(&old[..1000]);
+
+        let ops = diff(&old, &new);
+        assert_eq!(apply(&old, &ops).unwrap(), new);
+
+        let inserted: usize = ops
+            .iter()
+            .map(|op| match op {
+                Op::Insert(bytes) => bytes.len(),
+                _ => 0,
+            })
+            .sum();
+        assert!(inserted < 200);
+    }
+}
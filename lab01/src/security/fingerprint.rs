@@ -0,0 +1,139 @@
+//! Hardware fingerprint made of several components.
+//!
+//! Every component is hashed with the vendor secret on its own, so a machine
+//! is still recognized after a part of it is replaced: it matches when at
+//! least `MIN_MATCHES` of the recorded components are the same.
+
+use subtle::ConstantTimeEq;
+
+use crate::identity::{Component, IdentityError, MachineIdSource};
+use crate::token::token;
+
+/// Length of a component hash in bytes.
+const HASH_LEN: usize = 8;
+
+/// Length of a fingerprint in bytes.
+pub const FINGERPRINT_LEN: usize = HASH_LEN * Component::ALL.len();
+
+/// Matching components needed to accept a machine. Fingerprints with fewer
+/// recorded components need all of them to match.
+pub const MIN_MATCHES: usize = 3;
+
+/// Hash of an unknown component.
+const UNKNOWN: [u8; HASH_LEN] = [0; HASH_LEN];
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Fingerprint {
+    hashes: [[u8; HASH_LEN]; Component::ALL.len()],
+}
+
+fn component_hash(source: &dyn MachineIdSource, component: Component) -> [u8; HASH_LEN] {
+    match source.component(component) {
+        Some(value) => {
+            let token = token(&format!("{}:{}", component.name(), value));
+            token[..HASH_LEN].try_into().unwrap()
+        }
+        None => UNKNOWN,
+    }
+}
+
+impl Fingerprint {
+    /// Fingerprint of the machine reported by `source`. Only the machine id
+    /// is required, other components missing are recorded as unknown.
+    pub fn of(source: &dyn MachineIdSource) -> Result<Fingerprint, IdentityError> {
+        source.machine_id()?;
+
+        Ok(Fingerprint {
+            hashes: Component::ALL.map(|component| component_hash(source, component)),
+        })
+    }
+
+    pub fn to_hex(&self) -> String {
+        hex::encode(self.hashes.concat())
+    }
+
+    pub fn from_hex(s: &str) -> Option<Fingerprint> {
+        let bytes = hex::decode(s).ok()?;
+        if bytes.len() != FINGERPRINT_LEN {
+            return None;
+        }
+
+        let mut hashes = [UNKNOWN; Component::ALL.len()];
+        for (hash, chunk) in hashes.iter_mut().zip(bytes.chunks(HASH_LEN)) {
+            hash.copy_from_slice(chunk);
+        }
+
+        Some(Fingerprint { hashes })
+    }
+
+    /// Compares every component of the machine reported by `source` with the
+    /// fingerprint. Components the fingerprint does not record are `None`.
+    pub fn compare(
+        &self,
+        source: &dyn MachineIdSource,
+    ) -> Result<Vec<(Component, Option<bool>)>, IdentityError> {
+        source.machine_id()?;
+
+        Ok(self
+            .hashes
+            .iter()
+            .zip(Component::ALL)
+            .map(|(hash, component)| {
+                let matched = (*hash != UNKNOWN)
+                    .then(|| bool::from(hash.ct_eq(&component_hash(source, component))));
+                (component, matched)
+            })
+            .collect())
+    }
+
+    /// Checks the machine reported by `source` against the fingerprint.
+    pub fn matches(&self, source: &dyn MachineIdSource) -> Result<bool, IdentityError> {
+        let compared = self.compare(source)?;
+        let recorded = compared.iter().filter(|(_, m)| m.is_some()).count();
+        let matched = compared.iter().filter(|(_, m)| *m == Some(true)).count();
+
+        Ok(recorded > 0 && matched >= MIN_MATCHES.min(recorded))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    // Note this useful idiom: importing names from outer (for mod tests) scope.
+    use super::*;
+    use crate::identity::FixedSource;
+
+    fn machine() -> FixedSource {
+        FixedSource::new("01234567-89AB-CDEF-0123-456789ABCDEF")
+            .unwrap()
+            .with(Component::MacAddresses, "00:11:22:33:44:55")
+            .with(Component::DiskSerial, "S1234567")
+            .with(Component::CpuModel, "Some CPU @ 3.00GHz")
+    }
+
+    #[test]
+    fn test_tolerates_one_change() {
+        let fingerprint = Fingerprint::of(&machine()).unwrap();
+        let hex = fingerprint.to_hex();
+        assert_eq!(Fingerprint::from_hex(&hex).unwrap(), fingerprint);
+
+        assert!(fingerprint.matches(&machine()).unwrap());
+        assert!(fingerprint
+            .matches(&machine().with(Component::MacAddresses, "66:77:88:99:AA:BB"))
+            .unwrap());
+        assert!(!fingerprint
+            .matches(
+                &machine()
+                    .with(Component::MacAddresses, "66:77:88:99:AA:BB")
+                    .with(Component::DiskSerial, "S7654321")
+            )
+            .unwrap());
+
+        let uuid_only =
+            Fingerprint::of(&FixedSource::new("01234567-89AB-CDEF-0123-456789ABCDEF").unwrap())
+                .unwrap();
+        assert!(uuid_only.matches(&machine()).unwrap());
+        assert!(!uuid_only
+            .matches(&FixedSource::new("FEDCBA98-7654-3210-FEDC-BA9876543210").unwrap())
+            .unwrap());
+    }
+}
@@ -0,0 +1,485 @@
+//! Floating licenses leased from a server on the local machine.
+//!
+//! The server hands out at most `seats` leases, one per running instance,
+//! over a line based TCP protocol. A lease that is not renewed with a
+//! heartbeat within `ttl` seconds expires and its seat is given to the next
+//! instance asking for one.
+//!
+//! ```text
+//! > acquire <fingerprint> <nonce>
+//! < ok <lease> <ttl> <tag>
+//! > heartbeat <lease> <nonce>
+//! < ok <lease> <ttl> <tag>
+//! > release <lease>
+//! < released
+//! ```
+//!
+//! Any request may also be answered with `denied <reason>`. The tag is keyed
+//! with the vendor secret over the fingerprint, the client nonce, the lease
+//! and the ttl, so a server built without the secret can not grant leases.
+//!
+//! A server given its license stops granting and renewing leases once the
+//! license expires, reading the date through a `Clock` so that setting the
+//! system clock back does not keep it serving.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use subtle::ConstantTimeEq;
+
+use crate::clock::Clock;
+use crate::date::Date;
+use crate::fingerprint::Fingerprint;
+use crate::identity::{IdentityError, MachineIdSource};
+use crate::license::{License, LicenseError};
+use crate::token::keyed_digest;
+
+pub const DEFAULT_SERVER: &str = "127.0.0.1:7878";
+
+fn tag(fingerprint: &str, nonce: &str, lease: &str, ttl: u64) -> String {
+    hex::encode(keyed_digest(&[
+        b"lease:",
+        fingerprint.as_bytes(),
+        b":",
+        nonce.as_bytes(),
+        b":",
+        lease.as_bytes(),
+        b":",
+        ttl.to_string().as_bytes(),
+    ]))
+}
+
+fn random_hex() -> String {
+    hex::encode(rand::random::<[u8; 16]>())
+}
+
+struct Entry {
+    fingerprint: String,
+    expires: Instant,
+}
+
+/// Lease bookkeeping of the server.
+pub struct LeaseServer {
+    seats: usize,
+    ttl: Duration,
+    leases: HashMap<String, Entry>,
+    license: Option<(License, Clock)>,
+}
+
+impl LeaseServer {
+    pub fn new(seats: usize, ttl: Duration) -> LeaseServer {
+        LeaseServer {
+            seats,
+            ttl,
+            leases: HashMap::new(),
+            license: None,
+        }
+    }
+
+    /// Serves leases only while `license` is valid, by the time of `clock`.
+    pub fn license(mut self, license: License, clock: Clock) -> LeaseServer {
+        self.license = Some((license, clock));
+        self
+    }
+
+    fn check_license(&self) -> Result<(), LicenseError> {
+        match &self.license {
+            Some((license, clock)) => license.check_date(Date::from_unix(clock.now()?)),
+            None => Ok(()),
+        }
+    }
+
+    /// Number of leases that have not expired at `now`.
+    pub fn active(&mut self, now: Instant) -> usize {
+        self.leases.retain(|_, entry| entry.expires > now);
+        self.leases.len()
+    }
+
+    fn grant(&mut self, lease: String, fingerprint: String, nonce: &str, now: Instant) -> String {
+        let ttl = self.ttl.as_secs();
+        let reply = format!(
+            "ok {} {} {}",
+            lease,
+            ttl,
+            tag(&fingerprint, nonce, &lease, ttl)
+        );
+
+        self.leases.insert(
+            lease,
+            Entry {
+                fingerprint,
+                expires: now + self.ttl,
+            },
+        );
+        reply
+    }
+
+    fn acquire(&mut self, fingerprint: &str, nonce: &str, now: Instant) -> String {
+        if Fingerprint::from_hex(fingerprint).is_none() {
+            return "denied malformed fingerprint".to_string();
+        }
+
+        // Every instance takes a seat of its own, even on the same machine,
+        // so that releasing one lease does not free the seat of another.
+        if self.active(now) >= self.seats {
+            return format!("denied all {} seats are taken", self.seats);
+        }
+
+        self.grant(random_hex(), fingerprint.to_string(), nonce, now)
+    }
+
+    fn heartbeat(&mut self, lease: &str, nonce: &str, now: Instant) -> String {
+        self.active(now);
+
+        match self.leases.get(lease) {
+            Some(entry) => {
+                let fingerprint = entry.fingerprint.clone();
+                self.grant(lease.to_string(), fingerprint, nonce, now)
+            }
+            None => "denied lease expired".to_string(),
+        }
+    }
+
+    /// Answers a single request line.
+    pub fn handle(&mut self, request: &str, now: Instant) -> String {
+        let words: Vec<&str> = request.split_whitespace().collect();
+
+        if let ["acquire" | "heartbeat", ..] = words[..] {
+            if let Err(err) = self.check_license() {
+                return format!("denied {}", err);
+            }
+        }
+
+        match words[..] {
+            ["acquire", fingerprint, nonce] => self.acquire(fingerprint, nonce, now),
+            ["heartbeat", lease, nonce] => self.heartbeat(lease, nonce, now),
+            ["release", lease] => {
+                self.leases.remove(lease);
+                "released".to_string()
+            }
+            _ => "denied malformed request".to_string(),
+        }
+    }
+}
+
+fn serve_connection(stream: TcpStream, server: &Mutex<LeaseServer>) -> io::Result<()> {
+    let mut writer = stream.try_clone()?;
+
+    for line in BufReader::new(stream).lines() {
+        let reply = server.lock().unwrap().handle(&line?, Instant::now());
+        writeln!(writer, "{}", reply)?;
+    }
+
+    Ok(())
+}
+
+/// Serves leases on `listener` until it fails.
+pub fn serve(listener: TcpListener, server: LeaseServer) -> io::Result<()> {
+    let server = Arc::new(Mutex::new(server));
+
+    for stream in listener.incoming() {
+        let stream = stream?;
+        let server = server.clone();
+        thread::spawn(move || serve_connection(stream, &server));
+    }
+
+    Ok(())
+}
+
+#[derive(Debug)]
+pub enum LeaseError {
+    Unreachable(io::Error),
+    Denied(String),
+    Forged,
+    Identity(IdentityError),
+}
+
+impl fmt::Display for LeaseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LeaseError::Unreachable(err) => write!(f, "license server is unreachable: {}", err),
+            LeaseError::Denied(reason) => write!(f, "license server denied the lease: {}", reason),
+            LeaseError::Forged => write!(f, "license server is not trusted"),
+            LeaseError::Identity(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+fn request(server: &str, line: &str) -> Result<String, LeaseError> {
+    let exchange = || -> io::Result<String> {
+        let mut stream = TcpStream::connect(server)?;
+        stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+        writeln!(stream, "{}", line)?;
+
+        let mut reply = String::new();
+        BufReader::new(stream).read_line(&mut reply)?;
+        Ok(reply.trim().to_string())
+    };
+
+    exchange().map_err(LeaseError::Unreachable)
+}
+
+/// Sends a request answered with a lease and checks the reply is genuine.
+fn request_lease(
+    server: &str,
+    line: &str,
+    fingerprint: &str,
+    nonce: &str,
+) -> Result<(String, u64), LeaseError> {
+    let reply = request(server, line)?;
+
+    if let Some(reason) = reply.strip_prefix("denied ") {
+        return Err(LeaseError::Denied(reason.to_string()));
+    }
+
+    let words: Vec<&str> = reply.split(' ').collect();
+    let (lease, ttl, reply_tag) = match words[..] {
+        ["ok", lease, ttl, reply_tag] => (lease, ttl, reply_tag),
+        _ => return Err(LeaseError::Forged),
+    };
+    let ttl: u64 = ttl.parse().map_err(|_| LeaseError::Forged)?;
+
+    let expected = tag(fingerprint, nonce, lease, ttl);
+    if !bool::from(expected.as_bytes().ct_eq(reply_tag.as_bytes())) {
+        return Err(LeaseError::Forged);
+    }
+
+    Ok((lease.to_string(), ttl))
+}
+
+/// Seat held by this instance. It is released when dropped.
+#[derive(Debug)]
+pub struct Lease {
+    server: String,
+    fingerprint: String,
+    pub id: String,
+    pub ttl: u64,
+    stopped: Arc<AtomicBool>,
+    lost: Arc<AtomicBool>,
+}
+
+impl Lease {
+    pub fn acquire(server: &str, source: &dyn MachineIdSource) -> Result<Lease, LeaseError> {
+        let fingerprint = Fingerprint::of(source)
+            .map_err(LeaseError::Identity)?
+            .to_hex();
+        let nonce = random_hex();
+        let line = format!("acquire {} {}", fingerprint, nonce);
+        let (id, ttl) = request_lease(server, &line, &fingerprint, &nonce)?;
+
+        Ok(Lease {
+            server: server.to_string(),
+            fingerprint,
+            id,
+            ttl,
+            stopped: Arc::new(AtomicBool::new(false)),
+            lost: Arc::new(AtomicBool::new(false)),
+        })
+    }
+
+    /// Renews the lease for another `ttl` seconds.
+    pub fn heartbeat(&self) -> Result<(), LeaseError> {
+        heartbeat(&self.server, &self.id, &self.fingerprint)
+    }
+
+    /// Sends heartbeats in the background until the lease is dropped. An
+    /// unreachable server is retried until `ttl` has passed since the last
+    /// renewal, when the lease has expired on the server too. Then, or when
+    /// the server denies the lease, `on_lost` is called from the background
+    /// thread and `is_lost` reports it from then on.
+    pub fn keep_alive<F: FnOnce() + Send + 'static>(&self, on_lost: F) {
+        let server = self.server.clone();
+        let id = self.id.clone();
+        let fingerprint = self.fingerprint.clone();
+        let stopped = self.stopped.clone();
+        let lost = self.lost.clone();
+        let ttl = Duration::from_secs(self.ttl);
+        let period = Duration::from_secs((self.ttl / 3).max(1));
+
+        thread::spawn(move || {
+            let mut renewed = Instant::now();
+
+            loop {
+                thread::sleep(period);
+                if stopped.load(Ordering::Relaxed) {
+                    return;
+                }
+
+                match heartbeat(&server, &id, &fingerprint) {
+                    Ok(()) => renewed = Instant::now(),
+                    Err(LeaseError::Unreachable(_)) if renewed.elapsed() < ttl => {}
+                    Err(_) => {
+                        lost.store(true, Ordering::Relaxed);
+                        on_lost();
+                        return;
+                    }
+                }
+            }
+        });
+    }
+
+    pub fn is_lost(&self) -> bool {
+        self.lost.load(Ordering::Relaxed)
+    }
+}
+
+fn heartbeat(server: &str, id: &str, fingerprint: &str) -> Result<(), LeaseError> {
+    let nonce = random_hex();
+    let line = format!("heartbeat {} {}", id, nonce);
+    let (lease, _) = request_lease(server, &line, fingerprint, &nonce)?;
+
+    if lease != id {
+        return Err(LeaseError::Forged);
+    }
+    Ok(())
+}
+
+impl Drop for Lease {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::Relaxed);
+        // The lease expires on its own if the server can not be reached.
+        let _ = request(&self.server, &format!("release {}", self.id));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    // Note this useful idiom: importing names from outer (for mod tests) scope.
+    use super::*;
+    use crate::identity::FixedSource;
+
+    fn fingerprint(id: &str) -> String {
+        Fingerprint::of(&FixedSource::new(id).unwrap())
+            .unwrap()
+            .to_hex()
+    }
+
+    #[test]
+    fn test_seats_and_expiry() {
+        let mut server = LeaseServer::new(1, Duration::from_secs(60));
+        let start = Instant::now();
+        let first = fingerprint("01234567-89AB-CDEF-0123-456789ABCDEF");
+        let second = fingerprint("FEDCBA98-7654-3210-FEDC-BA9876543210");
+
+        let reply = server.handle(&format!("acquire {} n1", first), start);
+        assert!(reply.starts_with("ok "));
+        let lease = reply.split(' ').nth(1).unwrap().to_string();
+
+        // Every instance takes a seat, even on the same machine.
+        let again = server.handle(&format!("acquire {} n2", first), start);
+        assert!(again.starts_with("denied "));
+        let denied = server.handle(&format!("acquire {} n3", second), start);
+        assert!(denied.starts_with("denied "));
+
+        let later = start + Duration::from_secs(50);
+        let renewed = server.handle(&format!("heartbeat {} n4", lease), later);
+        assert!(renewed.starts_with("ok "));
+
+        let expired = start + Duration::from_secs(120);
+        let reply = server.handle(&format!("acquire {} n5", second), expired);
+        assert!(reply.starts_with("ok "));
+        let reply = server.handle(&format!("heartbeat {} n6", lease), expired);
+        assert!(reply.starts_with("denied "));
+    }
+
+    #[test]
+    fn test_expired_license() {
+        let path = std::env::temp_dir().join(format!("security-lease-{}", std::process::id()));
+        let clock = Clock::new(&path);
+        clock.start().unwrap();
+
+        let today = Date::today();
+        let license = License {
+            fingerprint: fingerprint("01234567-89AB-CDEF-0123-456789ABCDEF"),
+            issued: today.add_days(-30),
+            not_before: today.add_days(-30),
+            not_after: today.add_days(-1),
+            features: ["seats=1".to_string()].into(),
+        };
+        let mut server = LeaseServer::new(1, Duration::from_secs(60)).license(license, clock);
+
+        let reply = server.handle(
+            &format!(
+                "acquire {} n1",
+                fingerprint("FEDCBA98-7654-3210-FEDC-BA9876543210")
+            ),
+            Instant::now(),
+        );
+        assert!(reply.starts_with("denied license expired"));
+        assert_eq!(server.handle("release 00", Instant::now()), "released");
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_lease_over_tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        thread::spawn(move || serve(listener, LeaseServer::new(1, Duration::from_secs(60))));
+
+        let first = FixedSource::new("01234567-89AB-CDEF-0123-456789ABCDEF").unwrap();
+        let second = FixedSource::new("FEDCBA98-7654-3210-FEDC-BA9876543210").unwrap();
+
+        let lease = Lease::acquire(&addr, &first).unwrap();
+        lease.heartbeat().unwrap();
+        assert!(matches!(
+            Lease::acquire(&addr, &second),
+            Err(LeaseError::Denied(_))
+        ));
+
+        drop(lease);
+        Lease::acquire(&addr, &second).unwrap();
+    }
+
+    #[test]
+    fn test_keep_alive_outage() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let server = Mutex::new(LeaseServer::new(1, Duration::from_secs(3)));
+        // Only the acquire is answered, the server is gone after it.
+        let handle = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            serve_connection(stream, &server).unwrap();
+        });
+
+        let machine = FixedSource::new("01234567-89AB-CDEF-0123-456789ABCDEF").unwrap();
+        let lease = Lease::acquire(&addr, &machine).unwrap();
+        handle.join().unwrap();
+        lease.keep_alive(|| {});
+
+        thread::sleep(Duration::from_secs(2));
+        assert!(!lease.is_lost());
+        thread::sleep(Duration::from_secs(3));
+        assert!(lease.is_lost());
+    }
+
+    #[test]
+    fn test_instances_on_one_machine() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        thread::spawn(move || serve(listener, LeaseServer::new(2, Duration::from_secs(60))));
+
+        let machine = FixedSource::new("01234567-89AB-CDEF-0123-456789ABCDEF").unwrap();
+        let first = Lease::acquire(&addr, &machine).unwrap();
+        let second = Lease::acquire(&addr, &machine).unwrap();
+        assert_ne!(first.id, second.id);
+        assert!(matches!(
+            Lease::acquire(&addr, &machine),
+            Err(LeaseError::Denied(_))
+        ));
+
+        // The other instance keeps its seat when one exits.
+        drop(first);
+        second.heartbeat().unwrap();
+        let third = Lease::acquire(&addr, &machine).unwrap();
+
+        request(&addr, &format!("release {}", third.id)).unwrap();
+        assert!(matches!(third.heartbeat(), Err(LeaseError::Denied(_))));
+    }
+}
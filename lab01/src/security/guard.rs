@@ -0,0 +1,217 @@
+//! License check for binaries protected by the installer.
+//!
+//! A binary embeds a slot with `license_slot!` and checks it at startup:
+//!
+//! ```ignore
+//! security::license_slot!(SLOT);
+//!
+//! fn main() {
+//!     if let Err(err) = security::check_license(&SLOT) {
+//!         println!("FAILED: {}", err);
+//!         std::process::exit(1);
+//!     }
+//! }
+//! ```
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use rsa::{pkcs1::DecodeRsaPublicKey, RsaPublicKey};
+
+use crate::clock::Clock;
+use crate::date::Date;
+use crate::floating::{Lease, LeaseError};
+use crate::identity::{default_source, IdentityError, MachineIdSource};
+use crate::integrity::{verify_self, IntegrityError};
+use crate::license::{License, LicenseError};
+use crate::slot::{decode_slot, read_slot, KEY_LEN, KEY_TEMPLATE, SLOT_LEN};
+
+/// Declares the license slot the installer binds, as a static named `$name`.
+#[macro_export]
+macro_rules! license_slot {
+    ($name:ident) => {
+        // The section name must match `SLOT_SECTION`.
+        #[used]
+        #[link_section = ".licslot"]
+        static $name: [u8; $crate::SLOT_LEN] = $crate::KEY_TEMPLATE;
+    };
+}
+
+/// What the binary is allowed to run by.
+#[derive(Debug)]
+pub enum Grant {
+    /// The slot, bound to the machine fingerprint.
+    Slot(String),
+    License(License),
+    /// A seat leased from the floating license server.
+    Lease(Lease),
+}
+
+#[derive(Debug)]
+pub enum GuardError {
+    Unpatched,
+    WrongMachine,
+    Expired(Date),
+    Tampered(IntegrityError),
+    License(LicenseError),
+    Lease(LeaseError),
+    Identity(IdentityError),
+    /// The vendor public key given to `license_file` can not be read.
+    VendorKey(String),
+}
+
+impl fmt::Display for GuardError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GuardError::Unpatched => write!(f, "app is not installed"),
+            GuardError::WrongMachine => write!(f, "app is installed for another machine"),
+            GuardError::Expired(date) => write!(f, "license expired on {}", date),
+            GuardError::Tampered(err) => write!(f, "{}", err),
+            GuardError::License(err) => write!(f, "{}", err),
+            GuardError::Lease(err) => write!(f, "{}", err),
+            GuardError::Identity(err) => write!(f, "{}", err),
+            GuardError::VendorKey(err) => write!(f, "bad vendor key: {}", err),
+        }
+    }
+}
+
+impl From<LicenseError> for GuardError {
+    fn from(err: LicenseError) -> GuardError {
+        match err {
+            LicenseError::WrongMachine => GuardError::WrongMachine,
+            LicenseError::Expired(date) => GuardError::Expired(date),
+            LicenseError::Identity(err) => GuardError::Identity(err),
+            err => GuardError::License(err),
+        }
+    }
+}
+
+struct LicenseFile {
+    path: PathBuf,
+    last_seen: PathBuf,
+    public_key: RsaPublicKey,
+}
+
+pub struct LicenseGuard {
+    slot: &'static [u8; SLOT_LEN],
+    source: Box<dyn MachineIdSource>,
+    license_file: Option<LicenseFile>,
+    server_file: Option<PathBuf>,
+}
+
+impl LicenseGuard {
+    pub fn new(slot: &'static [u8; SLOT_LEN]) -> LicenseGuard {
+        LicenseGuard {
+            slot,
+            source: default_source(),
+            license_file: None,
+            server_file: None,
+        }
+    }
+
+    pub fn source(mut self, source: Box<dyn MachineIdSource>) -> LicenseGuard {
+        self.source = source;
+        self
+    }
+
+    /// The machine the guard checks against.
+    pub fn machine(&self) -> &dyn MachineIdSource {
+        &self.source
+    }
+
+    /// Accepts the license file at `path`, if it exists, signed with the
+    /// PKCS#1 PEM `public_key`. The last seen time is kept in `last_seen`.
+    pub fn license_file<P: AsRef<Path>, Q: AsRef<Path>>(
+        mut self,
+        path: P,
+        last_seen: Q,
+        public_key: &str,
+    ) -> Result<LicenseGuard, GuardError> {
+        let public_key = RsaPublicKey::from_pkcs1_pem(public_key)
+            .map_err(|err| GuardError::VendorKey(err.to_string()))?;
+
+        self.license_file = Some(LicenseFile {
+            path: path.as_ref().to_path_buf(),
+            last_seen: last_seen.as_ref().to_path_buf(),
+            public_key,
+        });
+        Ok(self)
+    }
+
+    /// Leases a seat from the server whose address is in the file at `path`,
+    /// if it exists, instead of checking the slot.
+    pub fn server_file<P: AsRef<Path>>(mut self, path: P) -> LicenseGuard {
+        self.server_file = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    fn check_license_file(&self, file: &LicenseFile) -> Result<License, GuardError> {
+        let content = std::fs::read_to_string(&file.path)
+            .map_err(|err| LicenseError::Malformed(err.to_string()))?;
+        let now = Clock::new(&file.last_seen).now()?;
+
+        Ok(License::verify(
+            &content,
+            &file.public_key,
+            &self.source,
+            Date::from_unix(now),
+        )?)
+    }
+
+    pub fn check(&self) -> Result<Grant, GuardError> {
+        let slot = read_slot(self.slot);
+        let unpatched = slot == KEY_TEMPLATE;
+
+        // An app that was never installed has nothing to check itself
+        // against, it can only run with a license file.
+        if !unpatched {
+            verify_self(&slot).map_err(GuardError::Tampered)?;
+        }
+
+        if let Some(path) = self.server_file.as_ref().filter(|p| p.exists()) {
+            let server = std::fs::read_to_string(path)
+                .map_err(|err| GuardError::Lease(LeaseError::Unreachable(err)))?;
+            let lease = Lease::acquire(server.trim(), &self.source).map_err(|err| match err {
+                LeaseError::Identity(err) => GuardError::Identity(err),
+                err => GuardError::Lease(err),
+            })?;
+            return Ok(Grant::Lease(lease));
+        }
+
+        if let Some(file) = self.license_file.as_ref().filter(|f| f.path.exists()) {
+            return Ok(Grant::License(self.check_license_file(file)?));
+        }
+
+        if unpatched {
+            return Err(GuardError::Unpatched);
+        }
+
+        if !crate::check(&slot, &self.source).map_err(GuardError::Identity)? {
+            return Err(GuardError::WrongMachine);
+        }
+
+        let payload = decode_slot(&slot);
+        Ok(Grant::Slot(
+            String::from_utf8_lossy(&payload[..KEY_LEN]).into_owned(),
+        ))
+    }
+}
+
+/// Checks the slot against the current machine.
+pub fn check_license(slot: &'static [u8; SLOT_LEN]) -> Result<Grant, GuardError> {
+    LicenseGuard::new(slot).check()
+}
+
+#[cfg(test)]
+mod tests {
+    // Note this useful idiom: importing names from outer (for mod tests) scope.
+    use super::*;
+
+    static SLOT: [u8; SLOT_LEN] = KEY_TEMPLATE;
+
+    #[test]
+    fn test_rejects_bad_vendor_key() {
+        let guard = LicenseGuard::new(&SLOT).license_file("license", "license.seen", "garbage");
+        assert!(matches!(guard, Err(GuardError::VendorKey(_))));
+    }
+}
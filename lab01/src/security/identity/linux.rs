@@ -0,0 +1,168 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use super::{normalize_uuid, Component, IdentityError, MachineIdSource};
+
+/// Sources are tried in order, the first one that exists is used. The
+/// systemd/dbus machine id comes first as every user can read it. The DMI
+/// UUID, the value WMI reports on Windows, is usually readable by root only,
+/// so preferring it would give the installer run with `sudo` and the app run
+/// by a user different ids.
+const SOURCES: [&str; 3] = [
+    "/etc/machine-id",
+    "/var/lib/dbus/machine-id",
+    "/sys/class/dmi/id/product_uuid",
+];
+
+/// Reads the machine id from `/etc` or the DMI product UUID from sysfs, and
+/// the other components from sysfs and `/proc`.
+pub struct SysfsSource;
+
+/// Machine id of the first of `sources` that can be read with `read`.
+fn first_machine_id<F>(sources: &[&str], read: F) -> Result<String, IdentityError>
+where
+    F: Fn(&str) -> io::Result<String>,
+{
+    // Reported when no source works, the most telling one wins.
+    let mut error = IdentityError::Unavailable("no machine id file found".to_string());
+
+    for path in sources.iter().copied() {
+        match read(path) {
+            Ok(content) if content.trim().is_empty() => {
+                if matches!(error, IdentityError::Unavailable(_)) {
+                    error = IdentityError::NoRows;
+                }
+            }
+            Ok(content) => match normalize_uuid(&content) {
+                Some(id) => return Ok(id),
+                None => error = IdentityError::MalformedUuid(content.trim().to_string()),
+            },
+            // Falling through would make the id depend on the user.
+            Err(err) if err.kind() == io::ErrorKind::PermissionDenied => {
+                return Err(IdentityError::PermissionDenied(path.to_string()));
+            }
+            Err(_) => {}
+        }
+    }
+
+    Err(error)
+}
+
+impl MachineIdSource for SysfsSource {
+    fn machine_id(&self) -> Result<String, IdentityError> {
+        first_machine_id(&SOURCES, |path| fs::read_to_string(path))
+    }
+
+    fn component(&self, component: Component) -> Option<String> {
+        match component {
+            Component::BoardUuid => self.machine_id().ok(),
+            Component::MacAddresses => mac_addresses(),
+            Component::DiskSerial => root_disk_serial(),
+            Component::CpuModel => cpu_model(),
+        }
+    }
+}
+
+/// Addresses of the physical network interfaces, that is the ones backed by a
+/// device, sorted and comma separated.
+fn mac_addresses() -> Option<String> {
+    let mut addresses: Vec<String> = fs::read_dir("/sys/class/net")
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.join("device").exists())
+        .filter_map(|path| fs::read_to_string(path.join("address")).ok())
+        .map(|address| address.trim().to_ascii_uppercase())
+        .collect();
+
+    if addresses.is_empty() {
+        return None;
+    }
+
+    addresses.sort();
+    Some(addresses.join(","))
+}
+
+/// Serial number of the disk `/` is mounted from.
+fn root_disk_serial() -> Option<String> {
+    let mounts = fs::read_to_string("/proc/mounts").ok()?;
+    let device = mounts.lines().find_map(|line| {
+        let mut fields = line.split_whitespace();
+        let device = fields.next()?;
+        (fields.next()? == "/").then_some(device)
+    })?;
+
+    let name = Path::new(device).file_name()?;
+    let mut block = fs::canonicalize(Path::new("/sys/class/block").join(name)).ok()?;
+    if block.join("partition").exists() {
+        block.pop();
+    }
+
+    ["device/serial", "device/wwid", "serial"]
+        .iter()
+        .filter_map(|file| fs::read_to_string(block.join(file)).ok())
+        .map(|serial| serial.trim().to_string())
+        .find(|serial| !serial.is_empty())
+}
+
+fn cpu_model() -> Option<String> {
+    let cpuinfo = fs::read_to_string("/proc/cpuinfo").ok()?;
+
+    cpuinfo.lines().find_map(|line| {
+        let (name, value) = line.split_once(':')?;
+        (name.trim() == "model name").then(|| value.trim().to_string())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    // Note this useful idiom: importing names from outer (for mod tests) scope.
+    use super::*;
+
+    const ID: &str = "0123456789abcdef0123456789abcdef";
+
+    /// Reads the fake files `files`, the missing ones failing with `missing`.
+    fn machine_id(files: &[(&str, &str)], missing: io::ErrorKind) -> Result<String, IdentityError> {
+        first_machine_id(&["a", "b", "c"], |path| {
+            match files.iter().find(|(name, _)| *name == path) {
+                Some((_, content)) => Ok(content.to_string()),
+                None => Err(io::Error::from(missing)),
+            }
+        })
+    }
+
+    #[test]
+    fn test_error_precedence() {
+        use io::ErrorKind::{NotFound, PermissionDenied};
+
+        assert!(matches!(
+            machine_id(&[], NotFound),
+            Err(IdentityError::Unavailable(_))
+        ));
+        assert_eq!(
+            machine_id(&[("b", "\n")], NotFound),
+            Err(IdentityError::NoRows)
+        );
+        assert_eq!(
+            machine_id(&[("a", "bad\n"), ("b", "\n")], NotFound),
+            Err(IdentityError::MalformedUuid("bad".to_string()))
+        );
+        assert_eq!(
+            machine_id(&[("b", "\n"), ("c", "bad")], NotFound),
+            Err(IdentityError::MalformedUuid("bad".to_string()))
+        );
+
+        // A source that can not be read stops the lookup, the ones after it
+        // are not used.
+        assert_eq!(
+            machine_id(&[("c", ID)], PermissionDenied),
+            Err(IdentityError::PermissionDenied("a".to_string()))
+        );
+
+        assert_eq!(
+            machine_id(&[("a", "\n"), ("b", ID), ("c", "bad")], NotFound).unwrap(),
+            "01234567-89AB-CDEF-0123-456789ABCDEF"
+        );
+    }
+}
@@ -0,0 +1,297 @@
+//! Machine identity providers. The platform one is picked at compile time and
+//! exposed as `PlatformSource`; the others exist for testing and debugging.
+
+use std::env;
+use std::fmt;
+
+#[cfg(windows)]
+mod windows;
+#[cfg(windows)]
+pub use self::windows::WmiSource as PlatformSource;
+
+#[cfg(target_os = "linux")]
+mod linux;
+#[cfg(target_os = "linux")]
+pub use self::linux::SysfsSource as PlatformSource;
+
+/// Name of the variable read by `EnvSource`.
+pub const MACHINE_ID_VAR: &str = "SECURITY_MACHINE_ID";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum IdentityError {
+    /// The provider could not be reached at all.
+    Unavailable(String),
+    /// The provider answered, but did not know the machine id.
+    NoRows,
+    MalformedUuid(String),
+    PermissionDenied(String),
+}
+
+impl IdentityError {
+    /// Exit code the binaries report the error with.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            IdentityError::Unavailable(_) => 2,
+            IdentityError::NoRows => 3,
+            IdentityError::MalformedUuid(_) => 4,
+            IdentityError::PermissionDenied(_) => 5,
+        }
+    }
+}
+
+impl fmt::Display for IdentityError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IdentityError::Unavailable(reason) => {
+                write!(f, "machine id provider is unavailable: {}", reason)
+            }
+            IdentityError::NoRows => write!(f, "machine id provider returned no machine id"),
+            IdentityError::MalformedUuid(id) => write!(f, "malformed machine id: {:?}", id),
+            IdentityError::PermissionDenied(what) => {
+                write!(f, "permission denied reading the machine id from {}", what)
+            }
+        }
+    }
+}
+
+/// Brings an id to the `XXXXXXXX-XXXX-XXXX-XXXX-XXXXXXXXXXXX` form used by WMI.
+pub fn normalize_uuid(content: &str) -> Option<String> {
+    let digits: String = content
+        .trim()
+        .chars()
+        .filter(|c| *c != '-')
+        .map(|c| c.to_ascii_uppercase())
+        .collect();
+
+    if digits.len() != 32 || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    Some(format!(
+        "{}-{}-{}-{}-{}",
+        &digits[0..8],
+        &digits[8..12],
+        &digits[12..16],
+        &digits[16..20],
+        &digits[20..32]
+    ))
+}
+
+/// Hardware components a machine is recognized by.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Component {
+    BoardUuid,
+    MacAddresses,
+    DiskSerial,
+    CpuModel,
+}
+
+impl Component {
+    pub const ALL: [Component; 4] = [
+        Component::BoardUuid,
+        Component::MacAddresses,
+        Component::DiskSerial,
+        Component::CpuModel,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Component::BoardUuid => "board-uuid",
+            Component::MacAddresses => "mac-addresses",
+            Component::DiskSerial => "disk-serial",
+            Component::CpuModel => "cpu-model",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Component> {
+        Component::ALL.into_iter().find(|c| c.name() == name)
+    }
+}
+
+pub trait MachineIdSource {
+    fn machine_id(&self) -> Result<String, IdentityError>;
+
+    /// Value of `component`, or `None` if the source can not tell it. Only the
+    /// board UUID, which is the machine id itself, is known by default.
+    fn component(&self, component: Component) -> Option<String> {
+        match component {
+            Component::BoardUuid => self.machine_id().ok(),
+            _ => None,
+        }
+    }
+}
+
+impl<T: MachineIdSource + ?Sized> MachineIdSource for Box<T> {
+    fn machine_id(&self) -> Result<String, IdentityError> {
+        (**self).machine_id()
+    }
+
+    fn component(&self, component: Component) -> Option<String> {
+        (**self).component(component)
+    }
+}
+
+/// Always reports the same id and components.
+pub struct FixedSource {
+    id: String,
+    components: Vec<(Component, String)>,
+}
+
+/// Normalizes an id given by the user, who may have copied it in any case.
+fn user_uuid(id: &str) -> Result<String, IdentityError> {
+    normalize_uuid(id).ok_or_else(|| IdentityError::MalformedUuid(id.trim().to_string()))
+}
+
+impl FixedSource {
+    /// Source reporting `id`, normalized like the platform ones do.
+    pub fn new(id: &str) -> Result<FixedSource, IdentityError> {
+        Ok(FixedSource {
+            id: user_uuid(id)?,
+            components: vec![],
+        })
+    }
+
+    pub fn with(mut self, component: Component, value: &str) -> FixedSource {
+        self.components.retain(|(c, _)| *c != component);
+        self.components.push((component, value.to_string()));
+        self
+    }
+
+    /// Reads a machine description written by `describe`. A bare id on a
+    /// single line is accepted as well.
+    pub fn parse(content: &str) -> Result<FixedSource, IdentityError> {
+        let content = content.trim();
+        if !content.contains(": ") {
+            return FixedSource::new(content);
+        }
+
+        let malformed = || IdentityError::MalformedUuid(content.to_string());
+        let mut components = vec![];
+        for line in content.lines() {
+            let (name, value) = line.split_once(": ").ok_or_else(malformed)?;
+            let component = Component::from_name(name).ok_or_else(malformed)?;
+            components.push((component, value.trim()));
+        }
+
+        let (_, id) = components
+            .iter()
+            .find(|(c, _)| *c == Component::BoardUuid)
+            .ok_or_else(malformed)?;
+        let source = components
+            .iter()
+            .filter(|(c, _)| *c != Component::BoardUuid)
+            .fold(FixedSource::new(id)?, |source, (c, value)| {
+                source.with(*c, value)
+            });
+
+        Ok(source)
+    }
+}
+
+impl MachineIdSource for FixedSource {
+    fn machine_id(&self) -> Result<String, IdentityError> {
+        Ok(self.id.clone())
+    }
+
+    fn component(&self, component: Component) -> Option<String> {
+        match self.components.iter().find(|(c, _)| *c == component) {
+            Some((_, value)) => Some(value.clone()),
+            None if component == Component::BoardUuid => Some(self.id.clone()),
+            None => None,
+        }
+    }
+}
+
+/// Reports the value of `MACHINE_ID_VAR` when it is set and asks `fallback`
+/// otherwise. When overridden, the other components are reported as unknown.
+pub struct EnvSource<S: MachineIdSource> {
+    fallback: S,
+}
+
+impl<S: MachineIdSource> EnvSource<S> {
+    pub fn new(fallback: S) -> EnvSource<S> {
+        EnvSource { fallback }
+    }
+}
+
+impl<S: MachineIdSource> MachineIdSource for EnvSource<S> {
+    fn machine_id(&self) -> Result<String, IdentityError> {
+        match env::var(MACHINE_ID_VAR) {
+            Ok(id) => user_uuid(&id),
+            Err(_) => self.fallback.machine_id(),
+        }
+    }
+
+    fn component(&self, component: Component) -> Option<String> {
+        match env::var(MACHINE_ID_VAR) {
+            Ok(id) if component == Component::BoardUuid => user_uuid(&id).ok(),
+            Ok(_) => None,
+            Err(_) => self.fallback.component(component),
+        }
+    }
+}
+
+/// Describes the machine reported by `source` as `name: value` lines, one per
+/// known component.
+pub fn describe(source: &dyn MachineIdSource) -> Result<String, IdentityError> {
+    source.machine_id()?;
+
+    Ok(Component::ALL
+        .iter()
+        .filter_map(|c| Some(format!("{}: {}\n", c.name(), source.component(*c)?)))
+        .collect())
+}
+
+/// The provider used by the binaries. The environment override is honoured in
+/// debug builds only, so that a release app cannot be fooled by setting it.
+pub fn default_source() -> Box<dyn MachineIdSource> {
+    if cfg!(debug_assertions) {
+        Box::new(EnvSource::new(PlatformSource))
+    } else {
+        Box::new(PlatformSource)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    // Note this useful idiom: importing names from outer (for mod tests) scope.
+    use super::*;
+
+    #[test]
+    fn test_describe_parse() {
+        let machine = FixedSource::new("01234567-89AB-CDEF-0123-456789ABCDEF")
+            .unwrap()
+            .with(Component::MacAddresses, "00:11:22:33:44:55")
+            .with(Component::CpuModel, "Some CPU @ 3.00GHz");
+
+        let parsed = FixedSource::parse(&describe(&machine).unwrap()).unwrap();
+        for component in Component::ALL {
+            assert_eq!(parsed.component(component), machine.component(component));
+        }
+
+        let bare = FixedSource::parse("01234567-89AB-CDEF-0123-456789ABCDEF\n").unwrap();
+        assert_eq!(bare.machine_id(), machine.machine_id());
+        assert!(FixedSource::parse("mac-addresses: 00:11:22:33:44:55").is_err());
+    }
+
+    #[test]
+    fn test_normalizes_ids() {
+        let id = "01234567-89AB-CDEF-0123-456789ABCDEF";
+        for copied in [
+            "01234567-89ab-cdef-0123-456789abcdef",
+            "0123456789abcdef0123456789ABCDEF\n",
+        ] {
+            assert_eq!(FixedSource::new(copied).unwrap().machine_id().unwrap(), id);
+            assert_eq!(
+                FixedSource::parse(copied).unwrap().machine_id().unwrap(),
+                id
+            );
+        }
+
+        assert_eq!(
+            FixedSource::new("garbage").err(),
+            Some(IdentityError::MalformedUuid("garbage".to_string()))
+        );
+        assert!(FixedSource::parse("board-uuid: garbage").is_err());
+    }
+}
@@ -0,0 +1,112 @@
+use wmi::{COMLibrary, WMIConnection, WMIError};
+
+use serde::Deserialize;
+
+use super::{normalize_uuid, Component, IdentityError, MachineIdSource};
+
+#[derive(Deserialize, Debug)]
+#[serde(rename = "Win32_OperatingSystem")]
+#[serde(rename_all = "PascalCase")]
+struct OperatingSystem {
+    uuid: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename = "Win32_NetworkAdapter")]
+#[serde(rename_all = "PascalCase")]
+struct NetworkAdapter {
+    #[serde(rename = "MACAddress")]
+    mac_address: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename = "Win32_DiskDrive")]
+#[serde(rename_all = "PascalCase")]
+struct DiskDrive {
+    serial_number: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename = "Win32_Processor")]
+#[serde(rename_all = "PascalCase")]
+struct Processor {
+    name: String,
+}
+
+/// Reads `Win32_ComputerSystemProduct.UUID` and the other components through
+/// WMI.
+pub struct WmiSource;
+
+/// `E_ACCESSDENIED` and `WBEM_E_ACCESS_DENIED`.
+const ACCESS_DENIED: [u32; 2] = [0x8007_0005, 0x8004_1003];
+
+fn identity_error(err: WMIError) -> IdentityError {
+    match err {
+        WMIError::HResultError { hres } if ACCESS_DENIED.contains(&(hres as u32)) => {
+            IdentityError::PermissionDenied("WMI".to_string())
+        }
+        err => IdentityError::Unavailable(err.to_string()),
+    }
+}
+
+fn connect() -> Result<WMIConnection, IdentityError> {
+    let com = COMLibrary::new().map_err(identity_error)?;
+    WMIConnection::new(com.into()).map_err(identity_error)
+}
+
+thread_local! {
+    // COM may only be initialized once per thread, so share the connection.
+    static WMI_CON: Result<WMIConnection, IdentityError> = connect();
+}
+
+fn query<T: for<'de> Deserialize<'de>>(query: &str) -> Result<Vec<T>, IdentityError> {
+    WMI_CON.with(|wmi_con| match wmi_con {
+        Ok(wmi_con) => wmi_con.raw_query(query).map_err(identity_error),
+        Err(err) => Err(err.clone()),
+    })
+}
+
+impl MachineIdSource for WmiSource {
+    fn machine_id(&self) -> Result<String, IdentityError> {
+        let results: Vec<OperatingSystem> = query("SELECT UUID FROM Win32_ComputerSystemProduct")?;
+        let uuid = results
+            .into_iter()
+            .next()
+            .ok_or(IdentityError::NoRows)?
+            .uuid;
+
+        normalize_uuid(&uuid).ok_or(IdentityError::MalformedUuid(uuid))
+    }
+
+    fn component(&self, component: Component) -> Option<String> {
+        match component {
+            Component::BoardUuid => self.machine_id().ok(),
+            Component::MacAddresses => {
+                let results: Vec<NetworkAdapter> = query(
+                    "SELECT MACAddress FROM Win32_NetworkAdapter WHERE PhysicalAdapter = TRUE",
+                )
+                .ok()?;
+                let mut addresses: Vec<String> =
+                    results.into_iter().filter_map(|a| a.mac_address).collect();
+                if addresses.is_empty() {
+                    return None;
+                }
+                addresses.sort();
+                Some(addresses.join(","))
+            }
+            Component::DiskSerial => {
+                let results: Vec<DiskDrive> =
+                    query("SELECT SerialNumber FROM Win32_DiskDrive WHERE Index = 0").ok()?;
+                results
+                    .into_iter()
+                    .next()?
+                    .serial_number
+                    .map(|s| s.trim().to_string())
+            }
+            Component::CpuModel => {
+                let results: Vec<Processor> = query("SELECT Name FROM Win32_Processor").ok()?;
+                Some(results.into_iter().next()?.name.trim().to_string())
+            }
+        }
+    }
+}
@@ -0,0 +1,241 @@
+//! Installing files into a target directory.
+//!
+//! Files are written next to their destination under a temporary name and
+//! renamed into place only once all of them were written, so an interrupted
+//! install does not leave a half written app behind. Every installed file is
+//! recorded with its SHA-256 in the manifest, which `uninstall` uses to remove
+//! exactly those files. Names may contain `/` to install into subdirectories.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use sha2::{Digest, Sha256};
+
+pub const MANIFEST_FILE: &str = "install.manifest";
+
+pub struct Installation {
+    dir: PathBuf,
+    staged: Vec<(String, PathBuf, String)>,
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    hex::encode(Sha256::digest(data))
+}
+
+fn temp_path(dir: &Path, name: &str) -> PathBuf {
+    let path = dir.join(name);
+    let file_name = path.file_name().unwrap().to_string_lossy();
+    path.with_file_name(format!(".{}.tmp", file_name))
+}
+
+/// Replaces the file at `path` with `data` at once, so that it is never seen
+/// half written, not even after a crash. The temporary name is unique, as
+/// several processes may replace the same file.
+pub(crate) fn replace_file(path: &Path, data: &[u8]) -> io::Result<()> {
+    let file_name = path.file_name().unwrap().to_string_lossy();
+    let temp = path.with_file_name(format!(
+        ".{}.{}-{:08x}.tmp",
+        file_name,
+        std::process::id(),
+        rand::random::<u32>()
+    ));
+
+    fs::write(&temp, data)?;
+    fs::rename(&temp, path).inspect_err(|_| {
+        let _ = fs::remove_file(&temp);
+    })
+}
+
+#[cfg(unix)]
+fn set_mode(path: &Path, mode: u32) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))
+}
+
+#[cfg(not(unix))]
+fn set_mode(_path: &Path, _mode: u32) -> io::Result<()> {
+    Ok(())
+}
+
+/// Reads the manifest of `dir`, as pairs of file name and hash.
+fn read_manifest(dir: &Path) -> io::Result<Vec<(String, String)>> {
+    let content = match fs::read_to_string(dir.join(MANIFEST_FILE)) {
+        Ok(content) => content,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(err) => return Err(err),
+    };
+
+    content
+        .lines()
+        .map(|line| {
+            let (hash, name) = line
+                .split_once("  ")
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "malformed manifest"))?;
+            Ok((name.to_string(), hash.to_string()))
+        })
+        .collect()
+}
+
+impl Installation {
+    pub fn new<P: AsRef<Path>>(dir: P) -> io::Result<Installation> {
+        fs::create_dir_all(&dir)?;
+
+        Ok(Installation {
+            dir: dir.as_ref().to_path_buf(),
+            staged: vec![],
+        })
+    }
+
+    /// Writes `data` to a temporary file, to be moved to `name` on commit.
+    pub fn put(&mut self, name: &str, data: &[u8], executable: bool) -> io::Result<()> {
+        self.stage(name, data, executable.then_some(0o755))
+    }
+
+    /// Like `put`, with the permissions of the file set to `mode` on Unix.
+    pub fn put_mode(&mut self, name: &str, data: &[u8], mode: u32) -> io::Result<()> {
+        self.stage(name, data, Some(mode))
+    }
+
+    fn stage(&mut self, name: &str, data: &[u8], mode: Option<u32>) -> io::Result<()> {
+        let temp = temp_path(&self.dir, name);
+        fs::create_dir_all(temp.parent().unwrap())?;
+        fs::write(&temp, data)?;
+        if let Some(mode) = mode {
+            set_mode(&temp, mode)?;
+        }
+
+        self.staged.push((name.to_string(), temp, sha256_hex(data)));
+        Ok(())
+    }
+
+    /// Moves the staged files into place and records them in the manifest.
+    /// When a file can not be moved, the ones moved before it are still
+    /// recorded, so that `uninstall` removes them, and the rest is dropped.
+    pub fn commit(mut self) -> io::Result<Vec<PathBuf>> {
+        let mut manifest = read_manifest(&self.dir)?;
+        let mut installed = vec![];
+        let mut result = Ok(());
+
+        for (name, temp, hash) in self.staged.iter() {
+            let path = self.dir.join(name);
+            if let Err(err) = fs::rename(temp, &path) {
+                result = Err(err);
+                break;
+            }
+            installed.push(path);
+
+            manifest.retain(|(n, _)| n != name);
+            manifest.push((name.clone(), hash.clone()));
+        }
+
+        let content: String = manifest
+            .iter()
+            .map(|(name, hash)| format!("{}  {}\n", hash, name))
+            .collect();
+        replace_file(&self.dir.join(MANIFEST_FILE), content.as_bytes())?;
+        result?;
+
+        self.staged.clear();
+        Ok(installed)
+    }
+}
+
+impl Drop for Installation {
+    fn drop(&mut self) {
+        for (_, temp, _) in self.staged.iter() {
+            let _ = fs::remove_file(temp);
+        }
+    }
+}
+
+/// Removes the files listed in the manifest of `dir`, then the manifest and
+/// the directories left empty, `dir` included. Files changed since the
+/// install are kept and returned.
+pub fn uninstall<P: AsRef<Path>>(dir: P) -> io::Result<Vec<PathBuf>> {
+    let dir = dir.as_ref();
+    let mut kept = vec![];
+
+    for (name, hash) in read_manifest(dir)? {
+        let path = dir.join(&name);
+        match fs::read(&path) {
+            Ok(data) if sha256_hex(&data) == hash => {
+                fs::remove_file(&path)?;
+                for parent in path.ancestors().skip(1).take_while(|p| *p != dir) {
+                    if fs::remove_dir(parent).is_err() {
+                        break;
+                    }
+                }
+            }
+            Ok(_) => kept.push(path),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err),
+        }
+    }
+
+    fs::remove_file(dir.join(MANIFEST_FILE))?;
+    let _ = fs::remove_dir(dir);
+
+    Ok(kept)
+}
+
+#[cfg(test)]
+mod tests {
+    // Note this useful idiom: importing names from outer (for mod tests) scope.
+    use super::*;
+
+    #[test]
+    fn test_install_uninstall() {
+        let dir = std::env::temp_dir().join(format!("security-install-{}", std::process::id()));
+
+        let mut installation = Installation::new(&dir).unwrap();
+        installation.put("app.exe", b"app", true).unwrap();
+        installation.put("README", b"readme", false).unwrap();
+        installation
+            .put_mode("etc/app.toml", b"config", 0o600)
+            .unwrap();
+        installation.commit().unwrap();
+
+        let mut installation = Installation::new(&dir).unwrap();
+        installation.put("app.exe", b"new app", true).unwrap();
+        installation.commit().unwrap();
+
+        assert_eq!(fs::read(dir.join("app.exe")).unwrap(), b"new app");
+        assert_eq!(read_manifest(&dir).unwrap().len(), 3);
+        assert_eq!(fs::read(dir.join("etc/app.toml")).unwrap(), b"config");
+
+        let staged = {
+            let mut installation = Installation::new(&dir).unwrap();
+            installation.put("other", b"other", false).unwrap();
+            temp_path(&dir, "other")
+        };
+        assert!(!staged.exists());
+
+        fs::write(dir.join("README"), b"edited").unwrap();
+        assert_eq!(uninstall(&dir).unwrap(), vec![dir.join("README")]);
+        assert!(!dir.join("app.exe").exists());
+        assert!(!dir.join("etc").exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_failed_commit() {
+        let dir = std::env::temp_dir().join(format!("security-commit-{}", std::process::id()));
+        fs::create_dir_all(dir.join("busy/file")).unwrap();
+
+        let mut installation = Installation::new(&dir).unwrap();
+        installation.put("app.exe", b"app", true).unwrap();
+        installation.put("busy", b"busy", false).unwrap();
+        installation.put("README", b"readme", false).unwrap();
+        assert!(installation.commit().is_err());
+
+        let manifest = read_manifest(&dir).unwrap();
+        assert_eq!(manifest.len(), 1);
+        assert_eq!(manifest[0].0, "app.exe");
+        assert!(!temp_path(&dir, "busy").exists());
+        assert!(!temp_path(&dir, "README").exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,73 @@
+//! Self-integrity of the installed app.
+//!
+//! The installer stores a keyed digest of the bound executable in the slot,
+//! after the fingerprint. It covers the whole file except the slot, plus the
+//! fingerprint, so neither the code nor the binding can be changed without
+//! the digest going stale.
+
+use std::fmt;
+use std::io;
+use std::ops::Range;
+
+use subtle::ConstantTimeEq;
+
+use crate::slot::{decode_slot, encode_slot, find_slot, SlotError, KEY_LEN, PAYLOAD_LEN, SLOT_LEN};
+use crate::token::{keyed_digest, TOKEN_LEN};
+
+#[derive(Debug)]
+pub enum IntegrityError {
+    Unreadable(io::Error),
+    Slot(SlotError),
+    Modified,
+}
+
+impl fmt::Display for IntegrityError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IntegrityError::Unreadable(err) => write!(f, "failed to read the executable: {}", err),
+            IntegrityError::Slot(err) => write!(f, "bad license slot: {}", err),
+            IntegrityError::Modified => write!(f, "executable was modified"),
+        }
+    }
+}
+
+fn image_digest(binary: &[u8], slot: Range<usize>, key: &[u8]) -> [u8; TOKEN_LEN] {
+    keyed_digest(&[key, &binary[..slot.start], &binary[slot.end..]])
+}
+
+/// Fills the slot of `binary` with `key`, the hex encoded fingerprint, and
+/// the digest of the image.
+pub fn seal(binary: &mut [u8], slot: Range<usize>, key: &[u8]) {
+    let digest = hex::encode(image_digest(binary, slot.clone(), key));
+
+    let mut payload = [0; PAYLOAD_LEN];
+    payload[..KEY_LEN].copy_from_slice(key);
+    payload[KEY_LEN..].copy_from_slice(digest.as_bytes());
+
+    binary[slot].copy_from_slice(&encode_slot(&payload));
+}
+
+/// Checks that `binary` is the sealed image holding `slot`.
+pub fn verify_image(binary: &[u8], slot: &[u8; SLOT_LEN]) -> Result<(), IntegrityError> {
+    let range = find_slot(binary).map_err(IntegrityError::Slot)?;
+    if binary[range.clone()] != slot[..] {
+        return Err(IntegrityError::Modified);
+    }
+
+    let payload = decode_slot(slot);
+    let digest = hex::encode(image_digest(binary, range, &payload[..KEY_LEN]));
+    if !bool::from(digest.as_bytes().ct_eq(&payload[KEY_LEN..])) {
+        return Err(IntegrityError::Modified);
+    }
+
+    Ok(())
+}
+
+/// Checks the executable of the running process, whose slot is `slot`.
+pub fn verify_self(slot: &[u8; SLOT_LEN]) -> Result<(), IntegrityError> {
+    let binary = std::env::current_exe()
+        .and_then(std::fs::read)
+        .map_err(IntegrityError::Unreadable)?;
+
+    verify_image(&binary, slot)
+}
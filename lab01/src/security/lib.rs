@@ -1,19 +1,66 @@
-use wmi::{COMLibrary, WMIConnection};
+mod activation;
+mod audit;
+mod bundle;
+mod clock;
+mod date;
+mod delta;
+mod fingerprint;
+mod floating;
+mod guard;
+mod identity;
+mod installation;
+mod integrity;
+mod license;
+mod signing;
+mod slot;
+mod token;
+mod update;
 
-use serde::Deserialize;
+pub use activation::{request_code, respond, verify_response};
+pub use audit::{AuditError, AuditLog, Record, AUDIT_FILE};
+pub use bundle::{Bundle, BundleError, BundleFile};
+pub use clock::Clock;
+pub use date::Date;
+pub use fingerprint::{Fingerprint, FINGERPRINT_LEN, MIN_MATCHES};
+pub use floating::{serve, Lease, LeaseError, LeaseServer, DEFAULT_SERVER};
+pub use guard::{check_license, Grant, GuardError, LicenseGuard};
+pub use identity::{
+    default_source, describe, normalize_uuid, Component, EnvSource, FixedSource, IdentityError,
+    MachineIdSource, PlatformSource, MACHINE_ID_VAR,
+};
+pub use installation::{uninstall, Installation, MANIFEST_FILE};
+pub use integrity::{seal, verify_image, verify_self, IntegrityError};
+pub use license::{License, LicenseError};
+pub use slot::{
+    decode_slot, encode_slot, find_slot, read_slot, SlotError, KEY_LEN, KEY_TEMPLATE, PAYLOAD_LEN,
+    SALT_LEN, SLOT_LEN, SLOT_SECTION,
+};
+pub use token::{token, verify_token, TOKEN_LEN};
+pub use update::{apply_patch, make_patch, UpdateError};
 
-#[derive(Deserialize, Debug)]
-#[serde(rename = "Win32_OperatingSystem")]
-#[serde(rename_all = "PascalCase")]
-struct OperatingSystem {
-    uuid: String,
+pub fn get_key() -> Result<String, IdentityError> {
+    default_source().machine_id()
 }
 
-pub fn get_key() -> String {
-    let wmi_con = WMIConnection::new(COMLibrary::new().unwrap().into()).unwrap();
-    let results: Vec<OperatingSystem> = wmi_con
-        .raw_query("SELECT UUID FROM Win32_ComputerSystemProduct")
-        .unwrap();
+/// Returns a copy of the unbound `app` binary with the key slot set to
+/// `fingerprint` and sealed.
+pub fn install(app: &[u8], fingerprint: &Fingerprint) -> Result<Vec<u8>, SlotError> {
+    let slot = find_slot(app)?;
 
-    results.into_iter().nth(0).unwrap().uuid
+    let mut installed = app.to_vec();
+    seal(&mut installed, slot, fingerprint.to_hex().as_bytes());
+
+    Ok(installed)
+}
+
+/// Checks the fingerprint in the app slot against the machine reported by
+/// `source`.
+pub fn check(slot: &[u8; SLOT_LEN], source: &dyn MachineIdSource) -> Result<bool, IdentityError> {
+    match std::str::from_utf8(&decode_slot(slot)[..KEY_LEN])
+        .ok()
+        .and_then(Fingerprint::from_hex)
+    {
+        Some(fingerprint) => fingerprint.matches(source),
+        None => Ok(false),
+    }
 }
@@ -0,0 +1,242 @@
+//! License files signed by the vendor.
+//!
+//! A license is a few `name: value` lines followed by the PKCS#1 v1.5
+//! signature of those lines:
+//!
+//! ```text
+//! fingerprint: <hex machine fingerprint>
+//! issued: 2022-09-01
+//! not-before: 2022-09-01
+//! not-after: 2023-09-01
+//! features: export,print
+//! signature: <hex>
+//! ```
+
+use std::collections::BTreeSet;
+use std::fmt;
+
+use rsa::{RsaPrivateKey, RsaPublicKey};
+
+use crate::date::Date;
+use crate::fingerprint::Fingerprint;
+use crate::identity::{IdentityError, MachineIdSource};
+use crate::signing::{sign, verify_signature};
+
+#[derive(Debug)]
+pub enum LicenseError {
+    Malformed(String),
+    BadSignature,
+    WrongMachine,
+    NotYetValid(Date),
+    Expired(Date),
+    ClockRollback,
+    Identity(IdentityError),
+}
+
+impl fmt::Display for LicenseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LicenseError::Malformed(reason) => write!(f, "malformed license: {}", reason),
+            LicenseError::BadSignature => write!(f, "license is not signed by the vendor"),
+            LicenseError::WrongMachine => write!(f, "license is issued for another machine"),
+            LicenseError::NotYetValid(date) => write!(f, "license is not valid until {}", date),
+            LicenseError::Expired(date) => write!(f, "license expired on {}", date),
+            LicenseError::ClockRollback => write!(f, "system clock was set back"),
+            LicenseError::Identity(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct License {
+    pub fingerprint: String,
+    pub issued: Date,
+    pub not_before: Date,
+    pub not_after: Date,
+    pub features: BTreeSet<String>,
+}
+
+impl License {
+    /// Issues a license for the machine reported by `source`, valid for `days`
+    /// days from today.
+    pub fn new<I: IntoIterator<Item = String>>(
+        source: &dyn MachineIdSource,
+        days: i64,
+        features: I,
+    ) -> Result<License, IdentityError> {
+        let issued = Date::today();
+
+        Ok(License {
+            fingerprint: Fingerprint::of(source)?.to_hex(),
+            issued,
+            not_before: issued,
+            not_after: issued.add_days(days),
+            features: features.into_iter().collect(),
+        })
+    }
+
+    pub fn allows(&self, feature: &str) -> bool {
+        self.features.contains(feature)
+    }
+
+    /// Number of concurrent seats, given by a `seats=N` feature, for
+    /// licenses served by the floating license server.
+    pub fn seats(&self) -> Option<usize> {
+        self.features
+            .iter()
+            .find_map(|feature| feature.strip_prefix("seats=")?.parse().ok())
+    }
+
+    /// Checks that `today` is within the validity period.
+    pub fn check_date(&self, today: Date) -> Result<(), LicenseError> {
+        if today < self.not_before {
+            return Err(LicenseError::NotYetValid(self.not_before));
+        }
+
+        if today > self.not_after {
+            return Err(LicenseError::Expired(self.not_after));
+        }
+
+        Ok(())
+    }
+
+    fn body(&self) -> String {
+        let features: Vec<&str> = self.features.iter().map(String::as_str).collect();
+
+        format!(
+            "fingerprint: {}\nissued: {}\nnot-before: {}\nnot-after: {}\nfeatures: {}\n",
+            self.fingerprint,
+            self.issued,
+            self.not_before,
+            self.not_after,
+            features.join(",")
+        )
+    }
+
+    /// Returns the content of the license file.
+    pub fn sign(&self, key: &RsaPrivateKey) -> String {
+        let body = self.body();
+        let sign = sign(key, body.as_bytes());
+
+        format!("{}signature: {}\n", body, hex::encode(sign))
+    }
+
+    fn parse(content: &str) -> Result<(License, Vec<u8>), LicenseError> {
+        let mut fingerprint = None;
+        let mut issued = None;
+        let mut not_before = None;
+        let mut not_after = None;
+        let mut features = None;
+        let mut sign = None;
+
+        for line in content.lines() {
+            let (name, value) = line
+                .split_once(": ")
+                .ok_or_else(|| LicenseError::Malformed(format!("bad line `{}`", line)))?;
+
+            match name {
+                "fingerprint" => fingerprint = Some(value.to_string()),
+                "issued" => issued = Some(value.parse().map_err(LicenseError::Malformed)?),
+                "not-before" => not_before = Some(value.parse().map_err(LicenseError::Malformed)?),
+                "not-after" => not_after = Some(value.parse().map_err(LicenseError::Malformed)?),
+                "features" => {
+                    features = Some(
+                        value
+                            .split(',')
+                            .filter(|f| !f.is_empty())
+                            .map(|f| f.to_string())
+                            .collect(),
+                    )
+                }
+                "signature" => {
+                    sign = Some(hex::decode(value).map_err(|_| LicenseError::BadSignature)?)
+                }
+                _ => return Err(LicenseError::Malformed(format!("unknown field `{}`", name))),
+            }
+        }
+
+        let missing = |name: &str| LicenseError::Malformed(format!("no `{}` field", name));
+        let license = License {
+            fingerprint: fingerprint.ok_or_else(|| missing("fingerprint"))?,
+            issued: issued.ok_or_else(|| missing("issued"))?,
+            not_before: not_before.ok_or_else(|| missing("not-before"))?,
+            not_after: not_after.ok_or_else(|| missing("not-after"))?,
+            features: features.ok_or_else(|| missing("features"))?,
+        };
+
+        Ok((license, sign.ok_or_else(|| missing("signature"))?))
+    }
+
+    /// Parses a license file and checks it was signed with the vendor key, is
+    /// issued for the machine reported by `source` and is valid on `today`.
+    /// `today` should come from a `Clock`, so a rolled back clock is noticed.
+    pub fn verify(
+        content: &str,
+        key: &RsaPublicKey,
+        source: &dyn MachineIdSource,
+        today: Date,
+    ) -> Result<License, LicenseError> {
+        let (license, sign) = License::parse(content)?;
+
+        if !verify_signature(key, license.body().as_bytes(), &sign) {
+            return Err(LicenseError::BadSignature);
+        }
+
+        let fingerprint =
+            Fingerprint::from_hex(&license.fingerprint).ok_or(LicenseError::WrongMachine)?;
+        if !fingerprint
+            .matches(source)
+            .map_err(LicenseError::Identity)?
+        {
+            return Err(LicenseError::WrongMachine);
+        }
+
+        license.check_date(today)?;
+
+        Ok(license)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    // Note this useful idiom: importing names from outer (for mod tests) scope.
+    use super::*;
+    use crate::identity::FixedSource;
+
+    #[test]
+    fn test_sign_verify() {
+        let mut rng = rand::thread_rng();
+        let private_key = RsaPrivateKey::new(&mut rng, 512).unwrap();
+        let public_key = private_key.to_public_key();
+        let machine = FixedSource::new("01234567-89AB-CDEF-0123-456789ABCDEF").unwrap();
+        let other = FixedSource::new("FEDCBA98-7654-3210-FEDC-BA9876543210").unwrap();
+        let today = Date::today();
+
+        let license = License::new(&machine, 30, vec!["export".to_string()]).unwrap();
+        let content = license.sign(&private_key);
+        let verified = License::verify(&content, &public_key, &machine, today).unwrap();
+        assert_eq!(verified, license);
+        assert!(verified.allows("export"));
+        assert!(!verified.allows("print"));
+
+        assert!(matches!(
+            License::verify(&content, &public_key, &other, today),
+            Err(LicenseError::WrongMachine)
+        ));
+
+        let forged = content.replace("features: export", "features: export,print");
+        assert!(matches!(
+            License::verify(&forged, &public_key, &machine, today),
+            Err(LicenseError::BadSignature)
+        ));
+
+        assert!(matches!(
+            License::verify(&content, &public_key, &machine, today.add_days(31)),
+            Err(LicenseError::Expired(_))
+        ));
+        assert!(matches!(
+            License::verify(&content, &public_key, &machine, today.add_days(-1)),
+            Err(LicenseError::NotYetValid(_))
+        ));
+    }
+}
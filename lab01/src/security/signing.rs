@@ -0,0 +1,24 @@
+//! PKCS#1 v1.5 signatures with the vendor RSA key, as in lab05.
+
+use rsa::{
+    pkcs1v15::{SigningKey, VerifyingKey},
+    RsaPrivateKey, RsaPublicKey,
+};
+use sha2::Sha256;
+use signature::{Signature, Signer, Verifier};
+
+pub fn sign(key: &RsaPrivateKey, data: &[u8]) -> Vec<u8> {
+    let signing_key = SigningKey::<Sha256>::new(key.clone());
+    let sign = signing_key.try_sign(data).expect("failed to sign data");
+
+    sign.as_bytes().to_vec()
+}
+
+pub fn verify_signature(key: &RsaPublicKey, data: &[u8], sign: &[u8]) -> bool {
+    let verifying_key = VerifyingKey::<Sha256>::from(key.clone());
+
+    match Signature::from_bytes(sign) {
+        Ok(sign) => verifying_key.verify(data, &sign).is_ok(),
+        Err(_) => false,
+    }
+}
@@ -0,0 +1,215 @@
+//! The license slot.
+//!
+//! The app keeps the slot in a linker section of its own, named
+//! `SLOT_SECTION`, so the installer can find it from the section headers of
+//! the executable instead of searching for the placeholder bytes, which may
+//! occur more than once.
+//!
+//! The slot holds the hex encoded machine fingerprint followed by the hex
+//! encoded digest of the installed executable. Both are XORed with a
+//! keystream derived from the vendor secret and a salt chosen on every
+//! install, which is stored in front of them, so the bound value can not be
+//! spotted in the binary.
+
+use std::fmt;
+use std::ops::Range;
+
+use object::{Object, ObjectSection};
+
+use rand::RngCore;
+
+use crate::fingerprint::FINGERPRINT_LEN;
+use crate::token::{keyed_digest, TOKEN_LEN};
+
+/// Section name, at most 8 bytes long to fit into a PE section header.
+pub const SLOT_SECTION: &str = ".licslot";
+
+/// Length of the salt at the start of the slot.
+pub const SALT_LEN: usize = 16;
+
+/// Length of the fingerprint part of the decoded slot.
+pub const KEY_LEN: usize = 2 * FINGERPRINT_LEN;
+
+/// Length of the decoded slot, the fingerprint followed by the digest.
+pub const PAYLOAD_LEN: usize = KEY_LEN + 2 * TOKEN_LEN;
+
+pub const SLOT_LEN: usize = SALT_LEN + PAYLOAD_LEN;
+
+/// Placeholder the installer replaces with the fingerprint and the digest.
+pub const KEY_TEMPLATE: [u8; SLOT_LEN] = [b'X'; SLOT_LEN];
+
+#[derive(Debug)]
+pub enum SlotError {
+    NotExecutable(object::Error),
+    Missing,
+    Duplicate(usize),
+    TooSmall(u64),
+    /// The section header points past the end of the file.
+    OutOfBounds(u64),
+}
+
+impl fmt::Display for SlotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SlotError::NotExecutable(err) => write!(f, "not an ELF or PE executable: {}", err),
+            SlotError::Missing => write!(f, "no `{}` section", SLOT_SECTION),
+            SlotError::Duplicate(n) => write!(f, "{} `{}` sections", n, SLOT_SECTION),
+            SlotError::TooSmall(size) => write!(
+                f,
+                "`{}` section has {} bytes, expected {}",
+                SLOT_SECTION, size, SLOT_LEN
+            ),
+            SlotError::OutOfBounds(offset) => write!(
+                f,
+                "`{}` section at {:#x} is past the end of the file",
+                SLOT_SECTION, offset
+            ),
+        }
+    }
+}
+
+/// Reads the slot of the running app. The compiler knows the initial value
+/// of the static, so a plain read could be folded into the placeholder.
+pub fn read_slot(slot: &[u8; SLOT_LEN]) -> [u8; SLOT_LEN] {
+    // SAFETY: `slot` is a valid reference, hence aligned and initialized.
+    unsafe { std::ptr::read_volatile(slot) }
+}
+
+/// XORs `data` with the keystream of `salt`: blocks of
+/// `HMAC(VENDOR_SECRET, "slot" || salt || counter)`.
+fn apply_keystream(salt: &[u8], data: &mut [u8]) {
+    for (i, chunk) in data.chunks_mut(TOKEN_LEN).enumerate() {
+        let block = keyed_digest(&[b"slot", salt, &(i as u32).to_le_bytes()]);
+        for (byte, key) in chunk.iter_mut().zip(block) {
+            *byte ^= key;
+        }
+    }
+}
+
+/// Encodes `payload` under a fresh salt.
+pub fn encode_slot(payload: &[u8; PAYLOAD_LEN]) -> [u8; SLOT_LEN] {
+    let mut slot = [0; SLOT_LEN];
+    let (salt, data) = slot.split_at_mut(SALT_LEN);
+
+    rand::thread_rng().fill_bytes(salt);
+    data.copy_from_slice(payload);
+    apply_keystream(salt, data);
+
+    slot
+}
+
+pub fn decode_slot(slot: &[u8; SLOT_LEN]) -> [u8; PAYLOAD_LEN] {
+    let (salt, data) = slot.split_at(SALT_LEN);
+
+    let mut payload: [u8; PAYLOAD_LEN] = data.try_into().unwrap();
+    apply_keystream(salt, &mut payload);
+
+    payload
+}
+
+/// Returns the position of the slot in the executable image `binary`.
+pub fn find_slot(binary: &[u8]) -> Result<Range<usize>, SlotError> {
+    let file = object::File::parse(binary).map_err(SlotError::NotExecutable)?;
+
+    let ranges: Vec<(u64, u64)> = file
+        .sections()
+        .filter(|section| section.name() == Ok(SLOT_SECTION))
+        .filter_map(|section| section.file_range())
+        .collect();
+
+    let (offset, size) = match ranges.as_slice() {
+        [] => return Err(SlotError::Missing),
+        [range] => *range,
+        _ => return Err(SlotError::Duplicate(ranges.len())),
+    };
+
+    // PE pads raw section data up to the file alignment.
+    if size < SLOT_LEN as u64 {
+        return Err(SlotError::TooSmall(size));
+    }
+
+    // The header may be forged, the slot must be within the file.
+    let end = usize::try_from(offset)
+        .ok()
+        .and_then(|start| start.checked_add(SLOT_LEN))
+        .filter(|end| *end <= binary.len())
+        .ok_or(SlotError::OutOfBounds(offset))?;
+
+    Ok(end - SLOT_LEN..end)
+}
+
+#[cfg(test)]
+mod tests {
+    // Note this useful idiom: importing names from outer (for mod tests) scope.
+    use super::*;
+
+    #[test]
+    fn test_encode_decode() {
+        let payload = [b'a'; PAYLOAD_LEN];
+
+        let slot = encode_slot(&payload);
+        assert_eq!(decode_slot(&slot), payload);
+        assert!(!slot.windows(4).any(|w| w == b"aaaa"));
+        assert_ne!(encode_slot(&payload), slot);
+    }
+
+    /// Minimal ELF64 executable with a `.shstrtab` and a `.licslot` section
+    /// whose header says it is at `offset`. The slot data itself is put
+    /// right after the section headers, at 320.
+    fn elf(offset: u64) -> Vec<u8> {
+        let mut elf = vec![0x7f, b'E', b'L', b'F', 2, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        elf.extend_from_slice(&2u16.to_le_bytes()); // ET_EXEC
+        elf.extend_from_slice(&62u16.to_le_bytes()); // x86-64
+        elf.extend_from_slice(&1u32.to_le_bytes());
+        elf.extend_from_slice(&0u64.to_le_bytes()); // entry
+        elf.extend_from_slice(&0u64.to_le_bytes()); // no program headers
+        elf.extend_from_slice(&128u64.to_le_bytes()); // section headers
+        elf.extend_from_slice(&0u32.to_le_bytes());
+        for field in [64u16, 56, 0, 64, 3, 1] {
+            elf.extend_from_slice(&field.to_le_bytes());
+        }
+
+        let names = b"\0.shstrtab\0.licslot\0";
+        elf.extend_from_slice(names);
+        elf.resize(128, 0);
+
+        let sections: [(u32, u32, u64, u64, u64); 3] = [
+            (0, 0, 0, 0, 0),
+            (1, 3, 0, 64, names.len() as u64),
+            (11, 1, 2, offset, SLOT_LEN as u64),
+        ];
+        for (name, kind, flags, offset, size) in sections {
+            elf.extend_from_slice(&name.to_le_bytes());
+            elf.extend_from_slice(&kind.to_le_bytes());
+            elf.extend_from_slice(&flags.to_le_bytes());
+            elf.extend_from_slice(&0u64.to_le_bytes());
+            elf.extend_from_slice(&offset.to_le_bytes());
+            elf.extend_from_slice(&size.to_le_bytes());
+            elf.extend_from_slice(&[0; 24]);
+        }
+
+        elf.extend_from_slice(&KEY_TEMPLATE);
+        elf
+    }
+
+    #[test]
+    fn test_find_slot() {
+        assert_eq!(find_slot(&elf(320)).unwrap(), 320..320 + SLOT_LEN);
+
+        let truncated = elf(320);
+        assert!(matches!(
+            find_slot(&truncated[..truncated.len() - 1]),
+            Err(SlotError::OutOfBounds(320))
+        ));
+
+        let near_end = elf(0).len() as u64 - 10;
+        assert!(matches!(
+            find_slot(&elf(near_end)),
+            Err(SlotError::OutOfBounds(_))
+        ));
+        assert!(matches!(
+            find_slot(&elf(u64::MAX - 5)),
+            Err(SlotError::OutOfBounds(_))
+        ));
+    }
+}
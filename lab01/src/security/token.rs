@@ -0,0 +1,49 @@
+//! Keyed tokens embedded into the app instead of the raw machine id.
+//!
+//! The token is `HMAC-SHA256(VENDOR_SECRET, machine id)`, so the bound id can
+//! not be read back from the binary, and a token copied from another machine
+//! does not match.
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Length of the token in bytes. It takes twice as much in the slot, since it
+/// is stored hex encoded.
+pub const TOKEN_LEN: usize = 32;
+
+/// Secret shared by the installer and the app, set with
+/// `SECURITY_VENDOR_SECRET` at compile time. Only debug builds may fall back
+/// to the development secret, which anyone reading the source knows.
+const VENDOR_SECRET: &str = match option_env!("SECURITY_VENDOR_SECRET") {
+    Some(secret) if !secret.is_empty() => secret,
+    _ if cfg!(debug_assertions) => "lab01 development secret",
+    _ => panic!("SECURITY_VENDOR_SECRET must be set for release builds"),
+};
+
+fn keyed() -> HmacSha256 {
+    HmacSha256::new_from_slice(VENDOR_SECRET.as_bytes()).unwrap()
+}
+
+pub fn token(machine_id: &str) -> [u8; TOKEN_LEN] {
+    let mut mac = keyed();
+    mac.update(machine_id.as_bytes());
+    mac.finalize().into_bytes().into()
+}
+
+/// Compares `token` with the one of `machine_id` in constant time.
+pub fn verify_token(machine_id: &str, token: &[u8]) -> bool {
+    let mut mac = keyed();
+    mac.update(machine_id.as_bytes());
+    mac.verify_slice(token).is_ok()
+}
+
+/// Keyed digest of the concatenation of `parts`.
+pub(crate) fn keyed_digest(parts: &[&[u8]]) -> [u8; TOKEN_LEN] {
+    let mut mac = keyed();
+    for part in parts {
+        mac.update(part);
+    }
+    mac.finalize().into_bytes().into()
+}
@@ -0,0 +1,109 @@
+//! Signed delta updates of installed apps.
+//!
+//! A patch is the diff between two unbound builds, signed by the vendor. An
+//! installed app differs from its unbound build only in the slot, so the
+//! installer puts the placeholder back, applies the patch and binds the new
+//! build to the fingerprint the old one had.
+
+use std::fmt;
+
+use rsa::{RsaPrivateKey, RsaPublicKey};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::delta::{apply, diff, Op};
+use crate::integrity::{seal, verify_image, IntegrityError};
+use crate::signing::{sign, verify_signature};
+use crate::slot::{decode_slot, find_slot, SlotError, KEY_LEN, KEY_TEMPLATE, SLOT_LEN};
+
+#[derive(Serialize, Deserialize)]
+struct Patch {
+    base: [u8; 32],
+    target: [u8; 32],
+    ops: Vec<Op>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SignedPatch {
+    patch: Vec<u8>,
+    signature: Vec<u8>,
+}
+
+#[derive(Debug)]
+pub enum UpdateError {
+    Malformed,
+    BadSignature,
+    Slot(SlotError),
+    Integrity(IntegrityError),
+    Unbound,
+    WrongBase,
+    Corrupted,
+}
+
+impl fmt::Display for UpdateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UpdateError::Malformed => write!(f, "malformed patch"),
+            UpdateError::BadSignature => write!(f, "patch is not signed by the vendor"),
+            UpdateError::Slot(err) => write!(f, "bad license slot: {}", err),
+            UpdateError::Integrity(err) => write!(f, "installed app is broken: {}", err),
+            UpdateError::Unbound => write!(f, "app is not installed"),
+            UpdateError::WrongBase => write!(f, "patch is for another version of the app"),
+            UpdateError::Corrupted => write!(f, "patched app does not match the patch"),
+        }
+    }
+}
+
+fn sha256(data: &[u8]) -> [u8; 32] {
+    Sha256::digest(data).into()
+}
+
+/// Vendor side: makes a patch turning the unbound build `old` into `new`.
+pub fn make_patch(old: &[u8], new: &[u8], key: &RsaPrivateKey) -> Vec<u8> {
+    let patch = Patch {
+        base: sha256(old),
+        target: sha256(new),
+        ops: diff(old, new),
+    };
+    let patch = postcard::to_allocvec(&patch).expect("failed to serialize patch");
+    let signature = sign(key, &patch);
+
+    postcard::to_allocvec(&SignedPatch { patch, signature }).expect("failed to serialize patch")
+}
+
+/// Applies `patch` to the `installed` app and returns the new app bound to
+/// the same machine.
+pub fn apply_patch(
+    installed: &[u8],
+    patch: &[u8],
+    key: &RsaPublicKey,
+) -> Result<Vec<u8>, UpdateError> {
+    let signed: SignedPatch = postcard::from_bytes(patch).map_err(|_| UpdateError::Malformed)?;
+    if !verify_signature(key, &signed.patch, &signed.signature) {
+        return Err(UpdateError::BadSignature);
+    }
+    let patch: Patch = postcard::from_bytes(&signed.patch).map_err(|_| UpdateError::Malformed)?;
+
+    let range = find_slot(installed).map_err(UpdateError::Slot)?;
+    let slot: [u8; SLOT_LEN] = installed[range.clone()].try_into().unwrap();
+    if slot == KEY_TEMPLATE {
+        return Err(UpdateError::Unbound);
+    }
+    verify_image(installed, &slot).map_err(UpdateError::Integrity)?;
+
+    let mut unbound = installed.to_vec();
+    unbound[range].copy_from_slice(&KEY_TEMPLATE);
+    if sha256(&unbound) != patch.base {
+        return Err(UpdateError::WrongBase);
+    }
+
+    let mut updated = apply(&unbound, &patch.ops).ok_or(UpdateError::Corrupted)?;
+    if sha256(&updated) != patch.target {
+        return Err(UpdateError::Corrupted);
+    }
+
+    let range = find_slot(&updated).map_err(UpdateError::Slot)?;
+    seal(&mut updated, range, &decode_slot(&slot)[..KEY_LEN]);
+
+    Ok(updated)
+}
@@ -0,0 +1,66 @@
+use rsa::{pkcs1::DecodeRsaPublicKey, RsaPublicKey};
+use security::{default_source, serve, Clock, Date, LeaseServer, License, DEFAULT_SERVER};
+use std::net::TcpListener;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use clap::Parser;
+
+const VENDOR_PUBLIC_KEY: &str = include_str!(env!("VENDOR_PUBLIC_KEY"));
+
+/// Floating license server, leasing the seats of a vendor license
+#[derive(Parser)]
+#[command(author, version, about, long_about = None)]
+struct Cli {
+    /// License issued for this machine with a `seats=N` feature
+    #[arg(short = 'L', long, default_value = "license")]
+    license: PathBuf,
+    /// Last seen time, written along with the license by the installer
+    #[arg(long, default_value = "license.seen")]
+    last_seen: PathBuf,
+    #[arg(short, long, default_value = DEFAULT_SERVER)]
+    listen: String,
+    /// Seconds a lease lives without a heartbeat
+    #[arg(short, long, default_value_t = 60)]
+    ttl: u64,
+}
+
+fn main() {
+    let cli = Cli::parse();
+    let public_key =
+        RsaPublicKey::from_pkcs1_pem(VENDOR_PUBLIC_KEY).expect("failed to read vendor key");
+    let content = std::fs::read_to_string(&cli.license).expect("failed to read license");
+
+    let clock = Clock::new(&cli.last_seen);
+    let verified = clock.now().and_then(|now| {
+        License::verify(
+            &content,
+            &public_key,
+            &default_source(),
+            Date::from_unix(now),
+        )
+    });
+    let license = match verified {
+        Ok(license) => license,
+        Err(err) => {
+            eprintln!("Failed to check the license: {}", err);
+            std::process::exit(1);
+        }
+    };
+    let seats = match license.seats() {
+        Some(seats) => seats,
+        None => {
+            eprintln!("License does not grant any seats");
+            std::process::exit(1);
+        }
+    };
+
+    let listener = TcpListener::bind(&cli.listen).expect("failed to listen");
+    println!(
+        "Serving {} seats on {} until {}",
+        seats, cli.listen, license.not_after
+    );
+
+    let server = LeaseServer::new(seats, Duration::from_secs(cli.ttl)).license(license, clock);
+    serve(listener, server).expect("failed to serve");
+}
@@ -0,0 +1,126 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use rsa::RsaPrivateKey;
+use security::{
+    apply_patch, install, make_patch, Fingerprint, FixedSource, IdentityError, SlotError,
+    MACHINE_ID_VAR,
+};
+
+const BOUND_ID: &str = "01234567-89AB-CDEF-0123-456789ABCDEF";
+const OTHER_ID: &str = "FEDCBA98-7654-3210-FEDC-BA9876543210";
+
+fn write_app(name: &str, bytes: &[u8]) -> PathBuf {
+    let path = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(name);
+    std::fs::write(&path, bytes).unwrap();
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    path
+}
+
+fn fingerprint(id: &str) -> Fingerprint {
+    Fingerprint::of(&FixedSource::new(id).unwrap()).unwrap()
+}
+
+fn install_for(name: &str, id: &str) -> PathBuf {
+    let app = std::fs::read(env!("CARGO_BIN_EXE_app")).unwrap();
+    write_app(name, &install(&app, &fingerprint(id)).unwrap())
+}
+
+fn run_as(app: &Path, id: &str) -> String {
+    let output = Command::new(app).env(MACHINE_ID_VAR, id).output().unwrap();
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn test_accepts_bound_machine() {
+    let app = install_for("app_accepts", BOUND_ID);
+    assert!(run_as(&app, BOUND_ID).starts_with("SUCCESS"));
+}
+
+#[test]
+fn test_rejects_other_machine() {
+    let app = install_for("app_rejects", BOUND_ID);
+    assert!(run_as(&app, OTHER_ID).starts_with("FAILED"));
+}
+
+#[test]
+fn test_reports_identity_errors() {
+    let app = install_for("app_identity", BOUND_ID);
+    let output = Command::new(&app)
+        .env(MACHINE_ID_VAR, "not a uuid")
+        .output()
+        .unwrap();
+    let malformed = IdentityError::MalformedUuid(String::new()).exit_code();
+    assert_eq!(output.status.code(), Some(malformed));
+    assert!(String::from_utf8(output.stdout)
+        .unwrap()
+        .starts_with("FAILED"));
+
+    let output = Command::new(env!("CARGO_BIN_EXE_installer"))
+        .args(["request", "--machine-id", "not a uuid"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(malformed));
+}
+
+#[test]
+fn test_rejects_modified_app() {
+    let app = std::fs::read(env!("CARGO_BIN_EXE_app")).unwrap();
+    let mut installed = install(&app, &fingerprint(BOUND_ID)).unwrap();
+    let last = installed.len() - 1;
+    installed[last] ^= 1;
+
+    let app = write_app("app_modified", &installed);
+    assert!(run_as(&app, BOUND_ID).starts_with("FAILED"));
+}
+
+#[test]
+fn test_does_not_embed_machine_id() {
+    let app = std::fs::read(env!("CARGO_BIN_EXE_app")).unwrap();
+    let installed = install(&app, &fingerprint(BOUND_ID)).unwrap();
+    assert!(!installed
+        .windows(BOUND_ID.len())
+        .any(|w| w == BOUND_ID.as_bytes()));
+
+    let fingerprint = fingerprint(BOUND_ID).to_hex();
+    assert!(!installed
+        .windows(fingerprint.len())
+        .any(|w| w == fingerprint.as_bytes()));
+}
+
+#[test]
+fn test_requires_slot_section() {
+    let installer = std::fs::read(env!("CARGO_BIN_EXE_installer")).unwrap();
+    assert!(matches!(
+        install(&installer, &fingerprint(BOUND_ID)),
+        Err(SlotError::Missing)
+    ));
+}
+
+#[test]
+fn test_update_keeps_activation() {
+    let mut rng = rand::thread_rng();
+    let private_key = RsaPrivateKey::new(&mut rng, 512).unwrap();
+
+    let old = std::fs::read(env!("CARGO_BIN_EXE_app")).unwrap();
+    let mut new = old.clone();
+    new.extend_from_slice(b"new version");
+
+    let installed = install(&old, &fingerprint(BOUND_ID)).unwrap();
+    let patch = make_patch(&old, &new, &private_key);
+    let updated = apply_patch(&installed, &patch, &private_key.to_public_key()).unwrap();
+    assert!(updated.ends_with(b"new version"));
+
+    let app = write_app("app_updated", &updated);
+    assert!(run_as(&app, BOUND_ID).starts_with("SUCCESS"));
+    assert!(run_as(&app, OTHER_ID).starts_with("FAILED"));
+
+    let other_key = RsaPrivateKey::new(&mut rng, 512).unwrap();
+    assert!(apply_patch(&installed, &patch, &other_key.to_public_key()).is_err());
+}
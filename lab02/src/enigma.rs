@@ -1,9 +1,78 @@
-use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+use rand_chacha::rand_core::{RngCore, SeedableRng};
+use rand_chacha::ChaCha20Rng;
+
+pub mod historical;
+pub mod key;
+pub mod legacy;
+pub mod stream;
+
+/// Source of the seeded wirings, fixed so that a seed keeps giving the same
+/// machine whatever the dependency versions.
+///
+/// The seed, as 8 little-endian bytes followed by 24 zero bytes, is the key
+/// of a ChaCha20 keystream with a zero nonce. Numbers are read from it as
+/// little-endian 32-bit words; a number below `n` is a word modulo `n`,
+/// skipping the words past the largest multiple of `n` so it is unbiased.
+struct SeedRng(ChaCha20Rng);
+
+impl SeedRng {
+    fn new(seed: u64) -> SeedRng {
+        let mut key = [0; 32];
+        key[..8].copy_from_slice(&seed.to_le_bytes());
+
+        SeedRng(ChaCha20Rng::from_seed(key))
+    }
+
+    /// Two words, the first one being the low half.
+    fn next_u64(&mut self) -> u64 {
+        let low = self.0.next_u32() as u64;
+        let high = self.0.next_u32() as u64;
+        (high << 32) | low
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        let n = n as u64;
+        let limit = (1 << 32) / n * n;
+
+        loop {
+            let x = self.0.next_u32() as u64;
+            if x < limit {
+                return (x % n) as usize;
+            }
+        }
+    }
+
+    /// Fisher-Yates shuffle, swapping every element from the last one down
+    /// with one at or before it.
+    fn shuffle(&mut self, items: &mut [usize]) {
+        for i in (1..items.len()).rev() {
+            let j = self.below(i + 1);
+            items.swap(i, j);
+        }
+    }
+}
+
+/// Inverse of the permutation `straight`, or `None` if it is not one.
+fn invert(straight: &[usize]) -> Option<Vec<usize>> {
+    let len = straight.len();
+    let mut backward: Vec<usize> = (0..len).map(|_| len).collect();
+
+    for (i, j) in straight.iter().enumerate() {
+        if *j >= len || backward[*j] != len {
+            return None;
+        }
+        backward[*j] = i;
+    }
+
+    Some(backward)
+}
 
 #[derive(Clone)]
 pub struct Rotor {
     len: usize,
     pos: usize,
+    ring: usize,
+    notches: Vec<usize>,
     straight: Vec<usize>,
     backward: Vec<usize>,
 }
@@ -11,34 +80,97 @@ pub struct Rotor {
 impl Rotor {
     pub fn from_seed(len: usize, seed: u64) -> Rotor {
         let mut straight: Vec<usize> = (0..len).collect();
-        let mut backward: Vec<usize> = (0..len).map(|_| 0).collect();
-        let mut rng = StdRng::seed_from_u64(seed);
+        SeedRng::new(seed).shuffle(&mut straight);
 
-        straight.as_mut_slice().shuffle(&mut rng);
+        // Carries to the next rotor once per revolution, like an odometer.
+        Rotor::from_wiring(straight, vec![len - 1]).unwrap()
+    }
 
-        for (i, j) in straight.iter().enumerate() {
-            backward[*j] = i;
+    /// Rotor mapping `i` to `wiring[i]` at position and ring setting 0. It
+    /// turns the next rotor over when stepping from one of the `notches`.
+    pub fn from_wiring(wiring: Vec<usize>, mut notches: Vec<usize>) -> Option<Rotor> {
+        let backward = invert(&wiring)?;
+        let len = wiring.len();
+        if notches.iter().any(|notch| *notch >= len) {
+            return None;
         }
+        notches.sort_unstable();
+        notches.dedup();
 
-        Rotor {
+        Some(Rotor {
             len,
             pos: 0,
-            straight,
+            ring: 0,
+            notches,
+            straight: wiring,
             backward,
+        })
+    }
+
+    /// The same rotor turning the next one over at `notches` instead.
+    pub fn with_notches(mut self, mut notches: Vec<usize>) -> Option<Rotor> {
+        if notches.iter().any(|notch| *notch >= self.len) {
+            return None;
         }
+        notches.sort_unstable();
+        notches.dedup();
+
+        self.notches = notches;
+        Some(self)
+    }
+
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+
+    pub fn set_position(&mut self, pos: usize) {
+        self.pos = pos % self.len;
+    }
+
+    /// Ring setting (Ringstellung), shifting the wiring against the position.
+    pub fn set_ring(&mut self, ring: usize) {
+        self.ring = ring % self.len;
     }
 
+    pub fn at_notch(&self) -> bool {
+        self.notches.contains(&self.pos)
+    }
+
+    /// Steps the rotor, returns whether it was at a notch.
     pub fn rotate(&mut self) -> bool {
+        let carry = self.at_notch();
         self.pos = (self.pos + 1) % self.len;
-        self.pos == 0
+        carry
+    }
+
+    /// Steps the rotor `steps` times at once, returns how many times it was
+    /// at a notch.
+    pub fn advance(&mut self, steps: u64) -> u64 {
+        let len = self.len as u64;
+        let carries = self
+            .notches
+            .iter()
+            .map(|notch| {
+                // Steps before the one from the notch.
+                let before = ((notch + self.len - self.pos) % self.len) as u64;
+                if steps > before {
+                    (steps - before - 1) / len + 1
+                } else {
+                    0
+                }
+            })
+            .sum();
+
+        self.pos = ((self.pos as u64 + steps % len) % len) as usize;
+        carries
     }
 
     fn _rotated(&self, x: usize) -> usize {
-        (self.pos + x) % self.len
+        (self.pos + x + self.len - self.ring) % self.len
     }
 
     fn _unrotated(&self, x: usize) -> usize {
-        (x + self.len - self.pos) % self.len
+        (x + self.len + self.ring - self.pos) % self.len
     }
 
     pub fn get_straight(&self, x: usize) -> usize {
@@ -50,21 +182,18 @@ impl Rotor {
     }
 }
 
+#[derive(Clone)]
 pub struct Reflector {
     reflects: Vec<usize>,
 }
 
 impl Reflector {
     pub fn from_seed(len: usize, seed: u64) -> Reflector {
-        let mut rng = StdRng::seed_from_u64(seed);
+        let mut rng = SeedRng::new(seed);
 
         let mut reflects: Vec<usize> = (0..len).map(|_| len).collect();
 
-        let stable = if (len & 1) == 0 {
-            len
-        } else {
-            rng.gen_range(0..len)
-        };
+        let stable = if (len & 1) == 0 { len } else { rng.below(len) };
 
         for i in 0..len {
             if reflects[i] != len {
@@ -77,7 +206,7 @@ impl Reflector {
             }
 
             loop {
-                let j = rng.gen_range(i..len);
+                let j = i + rng.below(len - i);
                 if j == stable || reflects[j] != len {
                     continue;
                 }
@@ -91,31 +220,248 @@ impl Reflector {
         Reflector { reflects }
     }
 
+    /// Reflector mapping `i` to `wiring[i]`, which must pair the symbols up.
+    pub fn from_wiring(wiring: Vec<usize>) -> Option<Reflector> {
+        let len = wiring.len();
+        let paired = |(i, j): (usize, &usize)| *j < len && wiring[*j] == i;
+        if !wiring.iter().enumerate().all(paired) {
+            return None;
+        }
+
+        Some(Reflector { reflects: wiring })
+    }
+
     pub fn get_reflect(&self, x: usize) -> usize {
         self.reflects[x]
     }
 }
 
+/// Swaps pairs of symbols on the way into and out of the rotors
+/// (Steckerbrett).
+#[derive(Clone)]
+pub struct Plugboard {
+    swaps: Vec<usize>,
+}
+
+impl Plugboard {
+    /// Plugboard without any cables.
+    pub fn new(len: usize) -> Plugboard {
+        Plugboard {
+            swaps: (0..len).collect(),
+        }
+    }
+
+    /// Plugboard swapping every pair. A symbol may only be in one pair.
+    pub fn from_pairs(len: usize, pairs: &[(usize, usize)]) -> Option<Plugboard> {
+        let mut plugboard = Plugboard::new(len);
+
+        for (a, b) in pairs.iter().copied() {
+            if a >= len || b >= len || a == b {
+                return None;
+            }
+            if plugboard.swaps[a] != a || plugboard.swaps[b] != b {
+                return None;
+            }
+
+            plugboard.swaps[a] = b;
+            plugboard.swaps[b] = a;
+        }
+
+        Some(plugboard)
+    }
+
+    /// Plugboard swapping pairs of bytes, as symbols of `alphabet`.
+    pub fn from_byte_pairs(alphabet: Alphabet, pairs: &[(u8, u8)]) -> Option<Plugboard> {
+        let pairs: Option<Vec<(usize, usize)>> = pairs
+            .iter()
+            .map(|(a, b)| Some((alphabet.symbol(*a)?, alphabet.symbol(*b)?)))
+            .collect();
+
+        Plugboard::from_pairs(alphabet.size(), &pairs?)
+    }
+
+    /// Parses pairs separated by whitespace, like `AB CD EF`.
+    pub fn parse(alphabet: Alphabet, s: &str) -> Option<Plugboard> {
+        let pairs: Option<Vec<(u8, u8)>> = s
+            .split_whitespace()
+            .map(|pair| match pair.as_bytes() {
+                [a, b] => Some((*a, *b)),
+                _ => None,
+            })
+            .collect();
+
+        Plugboard::from_byte_pairs(alphabet, &pairs?)
+    }
+
+    pub fn swap(&self, x: usize) -> usize {
+        self.swaps[x]
+    }
+}
+
+/// How the rotors advance on every symbol.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Stepping {
+    /// After every symbol, the first rotor steps and carries into the next
+    /// one at its notch.
+    Odometer,
+    /// Before every symbol, like the pawls of the historical machines: the
+    /// first rotor steps, and each of the next two steps when the rotor
+    /// before it is at a notch, pushing that one along as well (double
+    /// stepping). Rotors past the third one never step.
+    Pawls,
+}
+
+/// Number of rotors driven by pawls.
+const PAWLS: usize = 3;
+
+/// Symbols the machine works on.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Alphabet {
+    /// Every byte is a symbol.
+    Bytes,
+    /// Latin letters are enciphered keeping their case, other bytes are
+    /// passed through without stepping the rotors.
+    Letters,
+}
+
+impl Alphabet {
+    pub fn size(&self) -> usize {
+        match self {
+            Alphabet::Bytes => 256,
+            Alphabet::Letters => 26,
+        }
+    }
+
+    /// Symbol of `byte`, or `None` if it is not enciphered.
+    pub fn symbol(&self, byte: u8) -> Option<usize> {
+        match self {
+            Alphabet::Bytes => Some(byte.into()),
+            Alphabet::Letters => match byte {
+                b'A'..=b'Z' => Some((byte - b'A').into()),
+                b'a'..=b'z' => Some((byte - b'a').into()),
+                _ => None,
+            },
+        }
+    }
+}
+
+/// Rotors are listed from the entry side, the first one steps on every
+/// symbol.
+#[derive(Clone)]
 pub struct Enigma {
     rotors: Vec<Rotor>,
+    /// Positions the rotors started from.
+    start: Vec<usize>,
+    /// Symbols enciphered since then.
+    offset: u64,
     reflector: Reflector,
+    plugboard: Plugboard,
+    stepping: Stepping,
+    alphabet: Alphabet,
 }
 
 impl Enigma {
+    pub fn new(
+        rotors: Vec<Rotor>,
+        reflector: Reflector,
+        stepping: Stepping,
+        alphabet: Alphabet,
+    ) -> Enigma {
+        Enigma {
+            start: rotors.iter().map(Rotor::position).collect(),
+            offset: 0,
+            rotors,
+            reflector,
+            plugboard: Plugboard::new(alphabet.size()),
+            stepping,
+            alphabet,
+        }
+    }
+
+    pub fn with_plugboard(mut self, plugboard: Plugboard) -> Enigma {
+        self.plugboard = plugboard;
+        self
+    }
+
     pub fn from_seed(n_rotors: usize, seed: u64) -> Enigma {
-        let mut rng = StdRng::seed_from_u64(seed);
+        let mut rng = SeedRng::new(seed);
         let alphabet_size = 256;
 
         let rotors = (0..n_rotors)
-            .map(|_| Rotor::from_seed(alphabet_size, rng.gen()))
+            .map(|_| Rotor::from_seed(alphabet_size, rng.next_u64()))
             .collect();
-        let reflector = Reflector::from_seed(alphabet_size, rng.gen());
+        let reflector = Reflector::from_seed(alphabet_size, rng.next_u64());
+
+        Enigma::new(rotors, reflector, Stepping::Odometer, Alphabet::Bytes)
+    }
+
+    pub fn rotors(&self) -> &[Rotor] {
+        &self.rotors
+    }
+
+    /// Number of symbols enciphered since the start positions.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Puts the rotors where they are after `offset` symbols from the start
+    /// positions, without stepping through them. Only odometer stepping can
+    /// be computed this way, otherwise returns `false` leaving the rotors
+    /// as they are.
+    pub fn seek(&mut self, offset: u64) -> bool {
+        if self.stepping != Stepping::Odometer {
+            return false;
+        }
+
+        // Each rotor steps as many times as the one before it carried.
+        let mut steps = offset;
+        for (rot, start) in self.rotors.iter_mut().zip(&self.start) {
+            rot.set_position(*start);
+            steps = rot.advance(steps);
+        }
+
+        self.offset = offset;
+        true
+    }
+
+    fn _step(&mut self) {
+        match self.stepping {
+            Stepping::Odometer => {
+                for rot in self.rotors.iter_mut() {
+                    if !rot.rotate() {
+                        break;
+                    }
+                }
+            }
+            // A machine without rotors has nothing to step.
+            Stepping::Pawls if self.rotors.is_empty() => {}
+            Stepping::Pawls => {
+                let n = self.rotors.len().min(PAWLS);
+                let mut steps = vec![false; n];
+                steps[0] = true;
+
+                for i in 1..n {
+                    if self.rotors[i - 1].at_notch() {
+                        steps[i] = true;
+                        steps[i - 1] = true;
+                    }
+                }
 
-        Enigma { rotors, reflector }
+                for (rot, step) in self.rotors.iter_mut().zip(steps) {
+                    if step {
+                        rot.rotate();
+                    }
+                }
+            }
+        }
     }
 
     fn _process_one(&mut self, x: usize) -> usize {
-        let mut x = x;
+        if self.stepping == Stepping::Pawls {
+            self._step();
+        }
+
+        let mut x = self.plugboard.swap(x);
 
         for rot in self.rotors.iter() {
             x = rot.get_straight(x);
@@ -127,23 +473,208 @@ impl Enigma {
             x = rot.get_backward(x);
         }
 
-        for rot in self.rotors.iter_mut() {
-            if !rot.rotate() {
-                break;
-            }
+        x = self.plugboard.swap(x);
+
+        if self.stepping == Stepping::Odometer {
+            self._step();
         }
+        self.offset += 1;
 
         x
     }
 
+    fn _process_byte(&mut self, x: u8) -> u8 {
+        let symbol = match self.alphabet.symbol(x) {
+            Some(symbol) => symbol,
+            None => return x,
+        };
+        let y: u8 = self._process_one(symbol).try_into().unwrap();
+
+        match x {
+            b'A'..=b'Z' if self.alphabet == Alphabet::Letters => b'A' + y,
+            b'a'..=b'z' if self.alphabet == Alphabet::Letters => b'a' + y,
+            _ => y,
+        }
+    }
+
+    /// Enciphers `data` in place.
+    pub fn apply(&mut self, data: &mut [u8]) {
+        for x in data.iter_mut() {
+            *x = self._process_byte(*x);
+        }
+    }
+
+    /// Enciphers `data` in place, split into `jobs` chunks processed on
+    /// their own threads. The output is the same as of `apply`; machines
+    /// that can not `seek` process it on the current thread.
+    pub fn apply_parallel(&mut self, data: &mut [u8], jobs: usize) {
+        if jobs < 2 || self.stepping != Stepping::Odometer {
+            self.apply(data);
+            return;
+        }
+
+        let chunk_len = data.len().div_ceil(jobs).max(1);
+        let mut offset = self.offset;
+
+        std::thread::scope(|scope| {
+            for chunk in data.chunks_mut(chunk_len) {
+                let mut enigma = self.clone();
+                enigma.seek(offset);
+                offset += chunk
+                    .iter()
+                    .filter(|x| self.alphabet.symbol(**x).is_some())
+                    .count() as u64;
+
+                scope.spawn(move || enigma.apply(chunk));
+            }
+        });
+
+        self.seek(offset);
+    }
+
     pub fn run(&mut self, input: &[u8]) -> Vec<u8> {
-        input
-            .iter()
-            .map(|x| {
-                self._process_one((*x).try_into().unwrap())
-                    .try_into()
-                    .unwrap()
+        let mut output = input.to_vec();
+        self.apply(&mut output);
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    // Note this useful idiom: importing names from outer (for mod tests) scope.
+    use super::*;
+
+    #[test]
+    fn test_seed_stream() {
+        // First word of the ChaCha20 keystream for the zero key and nonce.
+        assert_eq!(SeedRng::new(0).0.next_u32(), 0xade0b876);
+    }
+
+    #[test]
+    fn test_golden_ciphertext() {
+        let mut enigma = Enigma::from_seed(3, 42);
+        assert_eq!(
+            hex(&enigma.run(b"Hello, world!")),
+            "83661ab7437a9865069cf8970d"
+        );
+
+        // Far enough for the second rotor to carry into the third one.
+        let mut enigma = Enigma::from_seed(3, 2022);
+        let ciphertext = enigma.run(&vec![0; 65540]);
+        assert_eq!(hex(&ciphertext[..8]), "320d69db0310776b");
+        assert_eq!(hex(&ciphertext[65532..]), "ab2a921f9e83fa65");
+    }
+
+    #[test]
+    fn test_plugboard() {
+        assert!(Plugboard::parse(Alphabet::Letters, "AB CD EF").is_some());
+        assert!(Plugboard::parse(Alphabet::Letters, "AB BC").is_none());
+        assert!(Plugboard::parse(Alphabet::Letters, "AA").is_none());
+        assert!(Plugboard::parse(Alphabet::Letters, "A1").is_none());
+
+        let plugboard = Plugboard::from_byte_pairs(Alphabet::Bytes, &[(0, 255)]).unwrap();
+        assert_eq!(plugboard.swap(255), 0);
+        assert_eq!(plugboard.swap(1), 1);
+    }
+
+    #[test]
+    fn test_no_rotors() {
+        for stepping in [Stepping::Odometer, Stepping::Pawls] {
+            let reflector = Reflector::from_seed(26, 1);
+            let mut enigma = Enigma::new(vec![], reflector, stepping, Alphabet::Letters);
+            let ciphertext = enigma.run(b"AAAA");
+            assert!(ciphertext.iter().all(|&c| c == ciphertext[0]));
+        }
+    }
+
+    #[test]
+    fn test_seek() {
+        let input: Vec<u8> = (0..70000).map(|i| (i * 31) as u8).collect();
+        let expected = Enigma::from_seed(3, 2022).run(&input);
+
+        for offset in [0, 255, 256, 65535, 65536, 69999] {
+            let mut enigma = Enigma::from_seed(3, 2022);
+            assert!(enigma.seek(offset));
+            let offset = offset as usize;
+            assert_eq!(enigma.run(&input[offset..]), expected[offset..]);
+        }
+
+        // Seeking back and with a fresh machine land on the same rotors.
+        let mut enigma = Enigma::from_seed(3, 2022);
+        enigma.run(&input);
+        enigma.seek(0);
+        assert_eq!(enigma.run(&input), expected);
+
+        let mut enigma = historical::machine("B", &["I", "II", "III"], "AAA", "AAA", "").unwrap();
+        assert!(!enigma.seek(10));
+    }
+
+    #[test]
+    fn test_seek_notches() {
+        let rotors = (0..3)
+            .map(|i| {
+                let mut rotor = Rotor::from_seed(26, i)
+                    .with_notches(vec![3, 11, 11, 20])
+                    .unwrap();
+                rotor.set_position(7 * i as usize);
+                rotor
             })
-            .collect()
+            .collect();
+        let mut enigma = Enigma::new(
+            rotors,
+            Reflector::from_seed(26, 3),
+            Stepping::Odometer,
+            Alphabet::Letters,
+        );
+
+        let input = b"the quick brown fox jumps over the lazy dog. ".repeat(100);
+        let expected = enigma.clone().run(&input);
+        for offset in 0..1000 {
+            let mut stepped = enigma.clone();
+            stepped.run(&input[..offset]);
+
+            let mut sought = enigma.clone();
+            sought.seek(stepped.offset());
+            assert_eq!(
+                sought
+                    .rotors()
+                    .iter()
+                    .map(Rotor::position)
+                    .collect::<Vec<_>>(),
+                stepped
+                    .rotors()
+                    .iter()
+                    .map(Rotor::position)
+                    .collect::<Vec<_>>()
+            );
+        }
+
+        let mut output = input.clone();
+        enigma.apply_parallel(&mut output, 7);
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    fn test_apply_parallel() {
+        let input: Vec<u8> = (0..200_003).map(|i| (i * 13) as u8).collect();
+        let mut enigma = Enigma::from_seed(4, 7);
+        let mut expected = enigma.clone();
+        let expected = [expected.run(&input), expected.run(&input)].concat();
+
+        for jobs in [1, 2, 3, 8] {
+            let mut parallel = enigma.clone();
+            let mut output = [input.clone(), input.clone()].concat();
+            let (first, second) = output.split_at_mut(input.len());
+            parallel.apply_parallel(first, jobs);
+            parallel.apply_parallel(second, jobs);
+            assert_eq!(output, expected);
+        }
+
+        enigma.apply_parallel(&mut [], 4);
+        assert_eq!(enigma.offset(), 0);
+    }
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
     }
 }
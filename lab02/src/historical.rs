@@ -0,0 +1,193 @@
+//! Rotors and reflectors of the Enigma I, M3 and M4 with their real wirings.
+//!
+//! Machines are set up the way operators read the key sheet: rotors from
+//! left to right, ring settings and starting positions as letters.
+
+use crate::{Alphabet, Enigma, Plugboard, Reflector, Rotor, Stepping};
+
+/// Name, wiring and notches of the rotors. Beta and Gamma are the thin
+/// fourth rotors of the M4 and do not step.
+const ROTORS: [(&str, &str, &str); 10] = [
+    ("I", "EKMFLGDQVZNTOWYHXUSPAIBRCJ", "Q"),
+    ("II", "AJDKSIRUXBLHWTMCQGZNPYFVOE", "E"),
+    ("III", "BDFHJLCPRTXVZNYEIWGAKMUSQO", "V"),
+    ("IV", "ESOVPZJAYQUIRHXLNFTGKDCMWB", "J"),
+    ("V", "VZBRGITYUPSDNHLXAWMJQOFECK", "Z"),
+    ("VI", "JPGVOUMFYQBENHZRDKASXLICTW", "ZM"),
+    ("VII", "NZJHGRCXMYSWBOUFAIVLPEKQDT", "ZM"),
+    ("VIII", "FKQHTLXOCBJSPDZRAMEWNIUYGV", "ZM"),
+    ("Beta", "LEYJVCNIXWPBQMDRTAKZGFUHOS", ""),
+    ("Gamma", "FSOKANUERHMBTIYCWLQPZXVGJD", ""),
+];
+
+/// Name and wiring of the reflectors (Umkehrwalze). The thin ones go with
+/// the M4.
+const REFLECTORS: [(&str, &str); 5] = [
+    ("A", "EJMZALYXVBWFCRQUONTSPIKHGD"),
+    ("B", "YRUHQSLDPXNGOKMIEBFZCWVJAT"),
+    ("C", "FVPJIAOYEDRZXWGCTKUQSBNMHL"),
+    ("B-thin", "ENKQAUYWJICOPBLMDXZVFTHRGS"),
+    ("C-thin", "RDOBJNTKVEHMLFCWZAXGYIPSUQ"),
+];
+
+/// Letters as symbols, `A` being 0. Case is ignored.
+pub fn letters(s: &str) -> Option<Vec<usize>> {
+    s.chars()
+        .map(|c| match c.to_ascii_uppercase() {
+            c @ 'A'..='Z' => Some(c as usize - 'A' as usize),
+            _ => None,
+        })
+        .collect()
+}
+
+pub fn rotor(name: &str) -> Option<Rotor> {
+    let (_, wiring, notches) = ROTORS.iter().find(|(n, _, _)| *n == name)?;
+
+    Rotor::from_wiring(letters(wiring)?, letters(notches)?)
+}
+
+pub fn reflector(name: &str) -> Option<Reflector> {
+    let (_, wiring) = REFLECTORS.iter().find(|(n, _)| *n == name)?;
+
+    Reflector::from_wiring(letters(wiring)?)
+}
+
+/// Sets up a machine from the key: the reflector, the rotors from left to
+/// right, e.g. `["I", "II", "III"]`, one letter per rotor for the ring
+/// settings and the starting positions, e.g. `"AAA"`, and the plugboard
+/// pairs, e.g. `"AV BS CG"`. A machine needs at least one rotor.
+pub fn machine(
+    reflector_name: &str,
+    rotors: &[&str],
+    rings: &str,
+    positions: &str,
+    plugs: &str,
+) -> Option<Enigma> {
+    let rings = letters(rings)?;
+    let positions = letters(positions)?;
+    if rotors.is_empty() || rings.len() != rotors.len() || positions.len() != rotors.len() {
+        return None;
+    }
+
+    // The machine lists rotors from the entry side, which is the right.
+    let mut set_up = vec![];
+    for ((name, ring), pos) in rotors.iter().zip(rings).zip(positions).rev() {
+        let mut rotor = rotor(name)?;
+        rotor.set_ring(ring);
+        rotor.set_position(pos);
+        set_up.push(rotor);
+    }
+
+    let enigma = Enigma::new(
+        set_up,
+        reflector(reflector_name)?,
+        Stepping::Pawls,
+        Alphabet::Letters,
+    );
+    Some(enigma.with_plugboard(Plugboard::parse(Alphabet::Letters, plugs)?))
+}
+
+/// Letters shown in the windows, from left to right.
+pub fn window(enigma: &Enigma) -> String {
+    enigma
+        .rotors()
+        .iter()
+        .rev()
+        .map(|rotor| (b'A' + rotor.position() as u8) as char)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    // Note this useful idiom: importing names from outer (for mod tests) scope.
+    use super::*;
+
+    fn encipher(enigma: &mut Enigma, text: &str) -> String {
+        String::from_utf8(enigma.run(text.as_bytes())).unwrap()
+    }
+
+    #[test]
+    fn test_enigma_i() {
+        let mut enigma = machine("B", &["I", "II", "III"], "AAA", "AAA", "").unwrap();
+        assert_eq!(encipher(&mut enigma, "AAAAA"), "BDZGO");
+
+        let mut enigma = machine("B", &["I", "II", "III"], "AAA", "AAA", "").unwrap();
+        assert_eq!(encipher(&mut enigma, "HELLOWORLD"), "ILBDAAMTAZ");
+
+        let mut enigma = machine("B", &["I", "II", "III"], "BBB", "AAA", "").unwrap();
+        assert_eq!(encipher(&mut enigma, "AAAAA"), "EWTYX");
+
+        let mut enigma = machine("B", &["I", "II", "III"], "AAA", "AAA", "").unwrap();
+        assert_eq!(encipher(&mut enigma, "Hello, world!"), "Ilbda, amtaz!");
+    }
+
+    #[test]
+    fn test_published_messages() {
+        // Enigma I operating manual, 1930.
+        let mut enigma =
+            machine("A", &["II", "I", "III"], "XMV", "ABL", "AM FI NV PS TU WZ").unwrap();
+        assert_eq!(
+            encipher(
+                &mut enigma,
+                "GCDSEAHUGWTQGRKVLFGXUCALXVYMIGMMNMFDXTGNVHVRMMEVOUYFZSLRHDRRXFJWCFHUHMUNZEFRDISIKBGPMYVXUZ"
+            ),
+            "FEINDLIQEINFANTERIEKOLONNEBEOBAQTETXANFANGSUEDAUSGANGBAERWALDEXENDEDREIKMOSTWAERTSNEUSTADT"
+        );
+
+        // Operation Barbarossa, 1941, first part.
+        let plugs = "AV BS CG DL FU HZ IN KM OW RX";
+        let mut enigma = machine("B", &["II", "IV", "V"], "BUL", "BLA", plugs).unwrap();
+        assert_eq!(
+            encipher(
+                &mut enigma,
+                "EDPUDNRGYSZRCXNUYTPOMRMBOFKTBZREZKMLXLVEFGUEYSIOZVEQMIKUBPMMYLKLTTDEISMDICAGYKUACTCDOMOHWXMUUIAUBSTSLRNBZSZWNRFXWFYSSXJZVIJHIDISHPRKLKAYUPADTXQSPINQMATLP"
+            ),
+            "AUFKLXABTEILUNGXVONXKURTINOWAXKURTINOWAXNORDWESTLXSEBEZXSEBEZXUAFFLIEGERSTRASZERIQTUNGXDUBROWKIXDUBROWKIXOPOTSCHKAXOPOTSCHKAXUMXEINSAQTDREINULLXUHRANGETR"
+        );
+    }
+
+    #[test]
+    fn test_bad_keys() {
+        assert!(machine("B", &[], "", "", "").is_none());
+        assert!(machine("B", &["I", "II"], "AAA", "AA", "").is_none());
+        assert!(machine("B", &["I", "IX"], "AA", "AA", "").is_none());
+        assert!(machine("B", &["I", "II"], "AA", "AA", "AB BC").is_none());
+    }
+
+    #[test]
+    fn test_double_stepping() {
+        let mut enigma = machine("B", &["I", "II", "III"], "AAA", "ADU", "").unwrap();
+        let mut windows = vec![];
+        for _ in 0..3 {
+            enigma.run(b"A");
+            windows.push(window(&enigma));
+        }
+
+        assert_eq!(windows, ["ADV", "AEW", "BFX"]);
+    }
+
+    #[test]
+    fn test_m4_matches_m3() {
+        // With Beta and Gamma at A, the thin reflectors act like B and C.
+        let text = "DASOBERKOMMANDODERWEHRMACHTGIBTBEKANNT";
+        for (m3, m4, greek) in [("B", "B-thin", "Beta"), ("C", "C-thin", "Gamma")] {
+            let mut enigma = machine(m3, &["VI", "VII", "VIII"], "CDE", "XYZ", "").unwrap();
+            let expected = encipher(&mut enigma, text);
+
+            let mut enigma =
+                machine(m4, &[greek, "VI", "VII", "VIII"], "ACDE", "AXYZ", "").unwrap();
+            assert_eq!(encipher(&mut enigma, text), expected);
+        }
+    }
+
+    #[test]
+    fn test_reciprocal() {
+        let text = "QWERTZUIOASDFGHJKPYXCVBNML";
+        let mut enigma = machine("C", &["II", "IV", "V"], "BUL", "BLA", "").unwrap();
+        let ciphertext = encipher(&mut enigma, text);
+        assert!(ciphertext.chars().zip(text.chars()).all(|(c, p)| c != p));
+
+        let mut enigma = machine("C", &["II", "IV", "V"], "BUL", "BLA", "").unwrap();
+        assert_eq!(encipher(&mut enigma, &ciphertext), text);
+    }
+}
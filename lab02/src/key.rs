@@ -0,0 +1,433 @@
+//! Key files, so operators can exchange daily keys.
+//!
+//! A key lists the rotors from left to right, each given by its historical
+//! name, its wiring or, for byte machines, the seed to generate it from:
+//!
+//! ```toml
+//! reflector = "B"
+//! plugboard = "AV BS CG DL FU HZ IN KM OW RX"
+//!
+//! [[rotors]]
+//! id = "II"
+//! ring = "B"
+//! position = "B"
+//!
+//! [[rotors]]
+//! wiring = "ESOVPZJAYQUIRHXLNFTGKDCMWB"
+//! notches = "J"
+//! ring = "U"
+//! position = "L"
+//!
+//! [[rotors]]
+//! id = "V"
+//! ring = "L"
+//! position = "A"
+//! ```
+//!
+//! Letter machines take letters for wirings, notches, ring settings and
+//! positions; byte machines (`alphabet = "bytes"`) take numbers. Keys made
+//! before seeds went through `SeedRng` need `seeding = "legacy"`.
+
+use std::fmt;
+
+use serde::Deserialize;
+
+use crate::historical::{self, letters};
+use crate::legacy;
+use crate::{Alphabet, Enigma, Plugboard, Reflector, Rotor, Stepping};
+
+#[derive(Debug)]
+pub enum KeyError {
+    Parse(String),
+    UnknownRotor(String),
+    UnknownReflector(String),
+    /// The rotor at the given index, from the left, is not a valid rotor.
+    BadRotor(usize),
+    BadReflector,
+    BadSetting(String),
+    /// Rings or positions were given for another number of rotors.
+    SettingCount {
+        expected: usize,
+        got: usize,
+    },
+    BadPlugboard(String),
+    NoRotors,
+}
+
+impl fmt::Display for KeyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            KeyError::Parse(reason) => write!(f, "malformed key: {}", reason),
+            KeyError::UnknownRotor(name) => write!(f, "unknown rotor {}", name),
+            KeyError::UnknownReflector(name) => write!(f, "unknown reflector {}", name),
+            KeyError::BadRotor(i) => write!(f, "rotor {} is not valid", i + 1),
+            KeyError::BadReflector => write!(f, "reflector does not pair the symbols up"),
+            KeyError::BadSetting(setting) => write!(f, "bad setting {:?}", setting),
+            KeyError::SettingCount { expected, got } => {
+                write!(f, "expected {} settings, got {}", expected, got)
+            }
+            KeyError::BadPlugboard(plugs) => write!(f, "bad plugboard {:?}", plugs),
+            KeyError::NoRotors => write!(f, "no rotors"),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum KeyAlphabet {
+    #[default]
+    Letters,
+    Bytes,
+}
+
+impl From<KeyAlphabet> for Alphabet {
+    fn from(alphabet: KeyAlphabet) -> Alphabet {
+        match alphabet {
+            KeyAlphabet::Letters => Alphabet::Letters,
+            KeyAlphabet::Bytes => Alphabet::Bytes,
+        }
+    }
+}
+
+/// A single symbol, as a letter or a number.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(untagged)]
+pub enum Setting {
+    Number(usize),
+    Letter(String),
+}
+
+/// Several symbols, as letters or numbers.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(untagged)]
+pub enum Symbols {
+    Numbers(Vec<usize>),
+    Letters(String),
+}
+
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct RotorKey {
+    pub id: Option<String>,
+    pub wiring: Option<Symbols>,
+    pub notches: Option<Symbols>,
+    pub seed: Option<u64>,
+    pub ring: Option<Setting>,
+    pub position: Option<Setting>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(untagged)]
+pub enum ReflectorKey {
+    Name(String),
+    Wiring { wiring: Symbols },
+    Seed { seed: u64 },
+}
+
+/// How seeds are turned into wirings.
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum KeySeeding {
+    #[default]
+    ChaCha20,
+    /// See `crate::legacy`.
+    Legacy,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Key {
+    #[serde(default)]
+    pub alphabet: KeyAlphabet,
+    #[serde(default)]
+    pub seeding: KeySeeding,
+    /// Defaults to pawls for letters and to an odometer for bytes.
+    pub stepping: Option<KeyStepping>,
+    pub reflector: ReflectorKey,
+    /// From left to right.
+    pub rotors: Vec<RotorKey>,
+    #[serde(default)]
+    pub plugboard: String,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum KeyStepping {
+    Odometer,
+    Pawls,
+}
+
+impl Symbols {
+    fn resolve(&self, alphabet: Alphabet) -> Option<Vec<usize>> {
+        let symbols = match self {
+            Symbols::Numbers(numbers) => numbers.clone(),
+            Symbols::Letters(s) if alphabet == Alphabet::Letters => letters(s)?,
+            Symbols::Letters(_) => return None,
+        };
+
+        symbols
+            .iter()
+            .all(|symbol| *symbol < alphabet.size())
+            .then_some(symbols)
+    }
+}
+
+impl Setting {
+    fn resolve(&self, alphabet: Alphabet) -> Result<usize, KeyError> {
+        let symbol = match self {
+            Setting::Number(number) => Some(*number),
+            Setting::Letter(s) if alphabet == Alphabet::Letters => match letters(s).as_deref() {
+                Some([letter]) => Some(*letter),
+                _ => None,
+            },
+            Setting::Letter(_) => None,
+        };
+
+        symbol
+            .filter(|symbol| *symbol < alphabet.size())
+            .ok_or_else(|| KeyError::BadSetting(format!("{:?}", self)))
+    }
+}
+
+/// Parses settings given on the command line: letters like `ADU` for letter
+/// machines and numbers like `0,17,255` for byte machines.
+pub fn parse_settings(alphabet: Alphabet, s: &str) -> Result<Vec<Setting>, KeyError> {
+    match alphabet {
+        Alphabet::Letters => Ok(s.chars().map(|c| Setting::Letter(c.to_string())).collect()),
+        Alphabet::Bytes => s
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|part| !part.is_empty())
+            .map(|part| {
+                part.parse()
+                    .map(Setting::Number)
+                    .map_err(|_| KeyError::BadSetting(part.to_string()))
+            })
+            .collect(),
+    }
+}
+
+impl RotorKey {
+    fn rotor(&self, alphabet: Alphabet, seeding: KeySeeding, i: usize) -> Result<Rotor, KeyError> {
+        let mut rotor = match (&self.id, &self.wiring, self.seed) {
+            (Some(id), None, None) if alphabet == Alphabet::Letters => {
+                let mut rotor =
+                    historical::rotor(id).ok_or_else(|| KeyError::UnknownRotor(id.clone()))?;
+                if let Some(notches) = &self.notches {
+                    rotor = rotor
+                        .with_notches(notches.resolve(alphabet).ok_or(KeyError::BadRotor(i))?)
+                        .ok_or(KeyError::BadRotor(i))?;
+                }
+                rotor
+            }
+            (None, Some(wiring), None) => {
+                let wiring = wiring.resolve(alphabet).ok_or(KeyError::BadRotor(i))?;
+                let notches = match &self.notches {
+                    Some(notches) => notches.resolve(alphabet).ok_or(KeyError::BadRotor(i))?,
+                    None => vec![],
+                };
+                if wiring.len() != alphabet.size() {
+                    return Err(KeyError::BadRotor(i));
+                }
+                Rotor::from_wiring(wiring, notches).ok_or(KeyError::BadRotor(i))?
+            }
+            (None, None, Some(seed)) if alphabet == Alphabet::Bytes => match seeding {
+                KeySeeding::ChaCha20 => Rotor::from_seed(alphabet.size(), seed),
+                KeySeeding::Legacy => legacy::rotor(alphabet.size(), seed),
+            },
+            _ => return Err(KeyError::BadRotor(i)),
+        };
+
+        if let Some(ring) = &self.ring {
+            rotor.set_ring(ring.resolve(alphabet)?);
+        }
+        if let Some(position) = &self.position {
+            rotor.set_position(position.resolve(alphabet)?);
+        }
+
+        Ok(rotor)
+    }
+}
+
+impl Key {
+    pub fn from_toml(content: &str) -> Result<Key, KeyError> {
+        toml::from_str(content).map_err(|err| KeyError::Parse(err.to_string()))
+    }
+
+    /// Replaces the rotors with the historical ones named by `ids`, keeping
+    /// the ring settings and positions of the rotors they replace.
+    pub fn set_rotors(&mut self, ids: &[&str]) {
+        self.rotors.resize_with(ids.len(), RotorKey::default);
+
+        for (rotor, id) in self.rotors.iter_mut().zip(ids) {
+            rotor.id = Some(id.to_string());
+            rotor.wiring = None;
+            rotor.notches = None;
+            rotor.seed = None;
+        }
+    }
+
+    fn check_count(&self, got: usize) -> Result<(), KeyError> {
+        if got != self.rotors.len() {
+            return Err(KeyError::SettingCount {
+                expected: self.rotors.len(),
+                got,
+            });
+        }
+        Ok(())
+    }
+
+    pub fn set_rings(&mut self, rings: Vec<Setting>) -> Result<(), KeyError> {
+        self.check_count(rings.len())?;
+
+        for (rotor, ring) in self.rotors.iter_mut().zip(rings) {
+            rotor.ring = Some(ring);
+        }
+        Ok(())
+    }
+
+    pub fn set_positions(&mut self, positions: Vec<Setting>) -> Result<(), KeyError> {
+        self.check_count(positions.len())?;
+
+        for (rotor, position) in self.rotors.iter_mut().zip(positions) {
+            rotor.position = Some(position);
+        }
+        Ok(())
+    }
+
+    /// Sets up the machine described by the key.
+    pub fn machine(&self) -> Result<Enigma, KeyError> {
+        let alphabet: Alphabet = self.alphabet.into();
+        if self.rotors.is_empty() {
+            return Err(KeyError::NoRotors);
+        }
+
+        // The machine lists rotors from the entry side, which is the right.
+        let rotors = self
+            .rotors
+            .iter()
+            .enumerate()
+            .rev()
+            .map(|(i, rotor)| rotor.rotor(alphabet, self.seeding, i))
+            .collect::<Result<Vec<Rotor>, KeyError>>()?;
+
+        let reflector = match &self.reflector {
+            ReflectorKey::Name(name) if alphabet == Alphabet::Letters => {
+                historical::reflector(name)
+                    .ok_or_else(|| KeyError::UnknownReflector(name.clone()))?
+            }
+            ReflectorKey::Name(name) => return Err(KeyError::UnknownReflector(name.clone())),
+            ReflectorKey::Wiring { wiring } => wiring
+                .resolve(alphabet)
+                .filter(|wiring| wiring.len() == alphabet.size())
+                .and_then(Reflector::from_wiring)
+                .ok_or(KeyError::BadReflector)?,
+            ReflectorKey::Seed { seed } if alphabet == Alphabet::Bytes => match self.seeding {
+                KeySeeding::ChaCha20 => Reflector::from_seed(alphabet.size(), *seed),
+                KeySeeding::Legacy => legacy::reflector(alphabet.size(), *seed),
+            },
+            ReflectorKey::Seed { .. } => return Err(KeyError::BadReflector),
+        };
+
+        let plugboard = Plugboard::parse(alphabet, &self.plugboard)
+            .ok_or_else(|| KeyError::BadPlugboard(self.plugboard.clone()))?;
+
+        let stepping = match (self.stepping, alphabet) {
+            (Some(KeyStepping::Odometer), _) | (None, Alphabet::Bytes) => Stepping::Odometer,
+            (Some(KeyStepping::Pawls), _) | (None, Alphabet::Letters) => Stepping::Pawls,
+        };
+
+        Ok(Enigma::new(rotors, reflector, stepping, alphabet).with_plugboard(plugboard))
+    }
+}
+
+impl Default for Key {
+    /// Enigma I with reflector B and no rotors yet.
+    fn default() -> Key {
+        Key {
+            alphabet: KeyAlphabet::Letters,
+            seeding: KeySeeding::ChaCha20,
+            stepping: None,
+            reflector: ReflectorKey::Name("B".to_string()),
+            rotors: vec![],
+            plugboard: String::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    // Note this useful idiom: importing names from outer (for mod tests) scope.
+    use super::*;
+
+    const BARBAROSSA: &str = r#"
+reflector = "B"
+plugboard = "AV BS CG DL FU HZ IN KM OW RX"
+
+[[rotors]]
+id = "II"
+ring = "B"
+position = "B"
+
+[[rotors]]
+wiring = "ESOVPZJAYQUIRHXLNFTGKDCMWB"
+notches = "J"
+ring = "U"
+position = "L"
+
+[[rotors]]
+id = "V"
+ring = "L"
+position = "A"
+"#;
+
+    #[test]
+    fn test_key_file() {
+        let key = Key::from_toml(BARBAROSSA).unwrap();
+        let mut enigma = key.machine().unwrap();
+        assert_eq!(enigma.run(b"EDPUDNRGYSZRCXN"), b"AUFKLXABTEILUNG");
+
+        let mut key = Key::default();
+        key.set_rotors(&["I", "II", "III"]);
+        key.set_positions(parse_settings(Alphabet::Letters, "AAA").unwrap())
+            .unwrap();
+        assert_eq!(key.machine().unwrap().run(b"AAAAA"), b"BDZGO");
+        let err = key
+            .set_rings(parse_settings(Alphabet::Letters, "AA").unwrap())
+            .unwrap_err();
+        assert_eq!(err.to_string(), "expected 3 settings, got 2");
+
+        let key = Key::from_toml(
+            r#"
+alphabet = "bytes"
+reflector = { seed = 7 }
+rotors = [{ seed = 1 }, { seed = 2, ring = 3, position = 200 }]
+plugboard = "ab"
+"#,
+        )
+        .unwrap();
+        let ciphertext = key.machine().unwrap().run(b"Hello, world!");
+        assert_eq!(key.machine().unwrap().run(&ciphertext), b"Hello, world!");
+
+        // The same key deciphers what it enciphered before `SeedRng`.
+        let key = Key::from_toml(
+            r#"
+alphabet = "bytes"
+seeding = "legacy"
+reflector = { seed = 7 }
+rotors = [{ seed = 1 }, { seed = 2, ring = 3, position = 200 }]
+"#,
+        )
+        .unwrap();
+        let old = [
+            0xaf, 0x57, 0x66, 0x4f, 0xbd, 0x87, 0x48, 0x69, 0xff, 0x35, 0xf3, 0xd6, 0x0e,
+        ];
+        assert_eq!(key.machine().unwrap().run(&old), b"Hello, world!");
+
+        assert!(
+            Key::from_toml("reflector = \"B\"\nrotors = [{ id = \"IX\" }]")
+                .unwrap()
+                .machine()
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,91 @@
+//! Seeded machines as they were made before `SeedRng`, straight from rand
+//! 0.8's `StdRng::seed_from_u64`. That stream is not promised to stay the
+//! same across rand versions, so it is only kept, with rand pinned, to
+//! decipher files enciphered with a seed back then.
+
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+
+use crate::{Alphabet, Enigma, Reflector, Rotor, Stepping};
+
+pub fn rotor(len: usize, seed: u64) -> Rotor {
+    let mut straight: Vec<usize> = (0..len).collect();
+    let mut rng = StdRng::seed_from_u64(seed);
+
+    straight.as_mut_slice().shuffle(&mut rng);
+
+    Rotor::from_wiring(straight, vec![len - 1]).unwrap()
+}
+
+pub fn reflector(len: usize, seed: u64) -> Reflector {
+    let mut rng = StdRng::seed_from_u64(seed);
+
+    let mut reflects: Vec<usize> = (0..len).map(|_| len).collect();
+
+    let stable = if (len & 1) == 0 {
+        len
+    } else {
+        rng.gen_range(0..len)
+    };
+
+    for i in 0..len {
+        if reflects[i] != len {
+            continue;
+        }
+
+        if i == stable {
+            reflects[i] = stable;
+            continue;
+        }
+
+        loop {
+            let j = rng.gen_range(i..len);
+            if j == stable || reflects[j] != len {
+                continue;
+            }
+
+            reflects[i] = j;
+            reflects[j] = i;
+            break;
+        }
+    }
+
+    Reflector::from_wiring(reflects).unwrap()
+}
+
+/// Like `Enigma::from_seed`.
+pub fn machine(n_rotors: usize, seed: u64) -> Enigma {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let alphabet_size = 256;
+
+    let rotors = (0..n_rotors)
+        .map(|_| rotor(alphabet_size, rng.gen()))
+        .collect();
+    let reflector = reflector(alphabet_size, rng.gen());
+
+    Enigma::new(rotors, reflector, Stepping::Odometer, Alphabet::Bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    // Note this useful idiom: importing names from outer (for mod tests) scope.
+    use super::*;
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    #[test]
+    fn test_old_ciphertext() {
+        // Made by the machines before the seeds went through `SeedRng`.
+        let mut enigma = machine(3, 42);
+        assert_eq!(
+            hex(&enigma.run(b"Hello, world!")),
+            "3e9cc78a152c7a0a5336183179"
+        );
+
+        let mut enigma = machine(3, 2022);
+        let ciphertext = enigma.run(&vec![0; 70000]);
+        assert_eq!(hex(&ciphertext[..8]), "f47209f26f38427e");
+        assert_eq!(hex(&ciphertext[69992..]), "5245fc9ce2867634");
+    }
+}
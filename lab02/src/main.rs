@@ -1,14 +1,131 @@
-mod enigma;
+use enigma::key::{parse_settings, Key, KeyError, KeySeeding, ReflectorKey};
+use enigma::stream::EnigmaReader;
+use enigma::{Alphabet, Enigma};
+use std::io::{Read, Write};
+use std::path::PathBuf;
+
+use clap::Parser;
+
+/// Bytes each thread enciphers at once with `--jobs`.
+const CHUNK_LEN: u64 = 1 << 20;
+
+#[derive(Parser)]
+#[command(author, version, about, long_about = None)]
+struct Cli {
+    /// Seed of a three rotor byte machine, instead of a key
+    #[arg(short, long, conflicts_with_all = ["key_file", "rotors", "rings", "positions", "reflector", "plugboard"])]
+    seed: Option<u64>,
+    /// Make seeded machines the way they were made before seeds went
+    /// through the ChaCha20 stream, to decipher files from back then
+    #[arg(long)]
+    legacy_seeding: bool,
+    /// Key file, see `enigma::key`
+    #[arg(short, long)]
+    key_file: Option<PathBuf>,
+    /// Historical rotors from left to right, e.g. "I II III"
+    #[arg(short, long)]
+    rotors: Option<String>,
+    /// Ring settings, e.g. "AAA"
+    #[arg(long)]
+    rings: Option<String>,
+    /// Starting positions, e.g. "ADU" or "0,17,255" for byte machines
+    #[arg(short, long)]
+    positions: Option<String>,
+    #[arg(long)]
+    reflector: Option<String>,
+    /// Plugboard pairs, e.g. "AV BS CG"
+    #[arg(long)]
+    plugboard: Option<String>,
+    /// Threads to encipher with, for machines with odometer stepping
+    #[arg(short, long, default_value_t = 1)]
+    jobs: usize,
+    /// Input file, `-` for stdin
+    input: PathBuf,
+    /// Output file, `-` for stdout
+    output: PathBuf,
+}
+
+fn machine(cli: &Cli) -> Result<Enigma, KeyError> {
+    if let Some(seed) = cli.seed {
+        if cli.legacy_seeding {
+            return Ok(enigma::legacy::machine(3, seed));
+        }
+        return Ok(Enigma::from_seed(3, seed));
+    }
+
+    let mut key = match &cli.key_file {
+        Some(path) => {
+            let content = std::fs::read_to_string(path).expect("failed to read key file");
+            Key::from_toml(&content)?
+        }
+        None => Key::default(),
+    };
+    let alphabet: Alphabet = key.alphabet.into();
+    if cli.legacy_seeding {
+        key.seeding = KeySeeding::Legacy;
+    }
+
+    if let Some(rotors) = &cli.rotors {
+        let ids: Vec<&str> = rotors.split_whitespace().collect();
+        key.set_rotors(&ids);
+    }
+    if let Some(rings) = &cli.rings {
+        key.set_rings(parse_settings(alphabet, rings)?)?;
+    }
+    if let Some(positions) = &cli.positions {
+        key.set_positions(parse_settings(alphabet, positions)?)?;
+    }
+    if let Some(reflector) = &cli.reflector {
+        key.reflector = ReflectorKey::Name(reflector.clone());
+    }
+    if let Some(plugboard) = &cli.plugboard {
+        key.plugboard = plugboard.clone();
+    }
+
+    key.machine()
+}
 
 fn main() {
-    let args: Vec<String> = std::env::args().collect();
+    let cli = Cli::parse();
+
+    let mut enigma = match machine(&cli) {
+        Ok(enigma) => enigma,
+        Err(err) => {
+            eprintln!("Failed to set up the machine: {}", err);
+            std::process::exit(1);
+        }
+    };
 
-    let seed = u64::from_str_radix(&args[1], 10).unwrap();
-    let inf = &args[2];
-    let outf: &String = &args[3];
+    let mut input: Box<dyn Read> = if cli.input.as_os_str() == "-" {
+        Box::new(std::io::stdin().lock())
+    } else {
+        Box::new(std::fs::File::open(&cli.input).expect("failed to open input"))
+    };
+    let mut output: Box<dyn Write> = if cli.output.as_os_str() == "-" {
+        Box::new(std::io::stdout().lock())
+    } else {
+        Box::new(std::fs::File::create(&cli.output).expect("failed to create output"))
+    };
 
-    let mut enigma = enigma::Enigma::from_seed(3, seed);
+    if cli.jobs > 1 {
+        let mut block = vec![];
+        loop {
+            block.clear();
+            input
+                .by_ref()
+                .take(cli.jobs as u64 * CHUNK_LEN)
+                .read_to_end(&mut block)
+                .expect("failed to read input");
+            if block.is_empty() {
+                break;
+            }
 
-    let input = std::fs::read(inf).unwrap();
-    std::fs::write(outf, enigma.run(&input)).unwrap();
+            enigma.apply_parallel(&mut block, cli.jobs);
+            output.write_all(&block).expect("failed to write output");
+        }
+    } else {
+        std::io::copy(&mut EnigmaReader::new(input, enigma), &mut output)
+            .expect("failed to encipher");
+    }
+    output.flush().expect("failed to write output");
 }
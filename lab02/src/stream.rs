@@ -0,0 +1,99 @@
+//! Enciphering streams, so that inputs of any size take constant memory.
+
+use std::io::{self, Read, Write};
+
+use crate::Enigma;
+
+/// Size of the buffer `EnigmaWriter` enciphers into.
+const BUFFER_LEN: usize = 8 * 1024;
+
+/// Enciphers what is read from the inner reader.
+pub struct EnigmaReader<R: Read> {
+    inner: R,
+    enigma: Enigma,
+}
+
+impl<R: Read> EnigmaReader<R> {
+    pub fn new(inner: R, enigma: Enigma) -> EnigmaReader<R> {
+        EnigmaReader { inner, enigma }
+    }
+
+    pub fn into_inner(self) -> (R, Enigma) {
+        (self.inner, self.enigma)
+    }
+}
+
+impl<R: Read> Read for EnigmaReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.enigma.apply(&mut buf[..n]);
+        Ok(n)
+    }
+}
+
+/// Enciphers what is written before passing it to the inner writer.
+///
+/// If the inner writer fails, the machine has already moved past the bytes
+/// of the failed call, so the stream can not be resumed.
+pub struct EnigmaWriter<W: Write> {
+    inner: W,
+    enigma: Enigma,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> EnigmaWriter<W> {
+    pub fn new(inner: W, enigma: Enigma) -> EnigmaWriter<W> {
+        EnigmaWriter {
+            inner,
+            enigma,
+            buffer: Vec::with_capacity(BUFFER_LEN),
+        }
+    }
+
+    pub fn into_inner(self) -> (W, Enigma) {
+        (self.inner, self.enigma)
+    }
+}
+
+impl<W: Write> Write for EnigmaWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = buf.len().min(BUFFER_LEN);
+
+        self.buffer.clear();
+        self.buffer.extend_from_slice(&buf[..n]);
+        self.enigma.apply(&mut self.buffer);
+        self.inner.write_all(&self.buffer)?;
+
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    // Note this useful idiom: importing names from outer (for mod tests) scope.
+    use super::*;
+
+    #[test]
+    fn test_streams_match_run() {
+        let input: Vec<u8> = (0..3 * BUFFER_LEN + 5).map(|i| (i * 7) as u8).collect();
+        let expected = Enigma::from_seed(3, 42).run(&input);
+
+        let mut output = vec![];
+        EnigmaReader::new(input.as_slice(), Enigma::from_seed(3, 42))
+            .read_to_end(&mut output)
+            .unwrap();
+        assert_eq!(output, expected);
+
+        let mut writer = EnigmaWriter::new(vec![], Enigma::from_seed(3, 42));
+        writer.write_all(&input).unwrap();
+        assert_eq!(writer.into_inner().0, expected);
+
+        let mut data = input;
+        Enigma::from_seed(3, 42).apply(&mut data);
+        assert_eq!(data, expected);
+    }
+}